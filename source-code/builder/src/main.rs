@@ -1,11 +1,28 @@
 use anyhow::{Result};
-use clap::{Parser, Subcommand};
-use hammer_core::{create_spinner, run_command, Logger};
-use owo_colors::OwoColorize;
+use clap::{Parser, Subcommand, ValueEnum};
+use hammer_core::{calculate_dir_size, create_progress_bar, create_spinner, format_size, run_command, Logger};
+use dialoguer::Confirm;
+use owo_colors::{OwoColorize, Stream};
 use nix::unistd::Uid;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
 use std::fs;
 
+/// Top-level live-build stages in the order `lb build` runs them
+const LB_STAGES: &[(&str, &str)] = &[
+    ("bootstrap", "Bootstrapping base system"),
+    ("chroot", "Customizing chroot"),
+    ("installer", "Adding installer"),
+    ("binary", "Building binary image"),
+    ("source", "Building source image"),
+];
+/// Full `lb build` output goes here when it is not shown live
+const BUILD_LOG: &str = "build.log";
+
 #[derive(Parser)]
 #[command(name = "hammer-builder")]
 struct Cli {
@@ -26,6 +43,24 @@ enum Commands {
         /// Path to source configuration directory (will be copied to ./config)
         #[arg(long)]
         config: Option<String>,
+
+        /// GPG key to sign the ISO with, writes <output>.sig
+        #[arg(long, value_name = "KEY_ID")]
+        sign: Option<String>,
+
+        /// Show raw live-build output instead of a progress bar
+        #[arg(long)]
+        verbose: bool,
+
+        /// Target architecture, passed to 'lb config --architecture'
+        #[arg(long, value_enum)]
+        arch: Option<Arch>,
+    },
+    /// Remove live-build state and built images without rebuilding
+    Clean {
+        /// Do not ask for confirmation
+        #[arg(long)]
+        yes: bool,
     },
     /// Generate static deltas for OSTree repository
     Delta {
@@ -35,6 +70,26 @@ enum Commands {
     },
 }
 
+/// Architectures live-build can build images for.
+#[derive(Clone, Copy, ValueEnum)]
+enum Arch {
+    Amd64,
+    I386,
+    Arm64,
+}
+
+impl Arch {
+    const ALL: [Arch; 3] = [Arch::Amd64, Arch::I386, Arch::Arm64];
+
+    fn name(self) -> &'static str {
+        match self {
+            Arch::Amd64 => "amd64",
+            Arch::I386 => "i386",
+            Arch::Arm64 => "arm64",
+        }
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
     
@@ -45,7 +100,7 @@ fn main() -> Result<()> {
             run_command("lb", &["config"], "Live Build Config")?;
             Logger::success("Build environment initialized. Edit ./config to customize.");
         }
-        Commands::Build { output, config } => {
+        Commands::Build { output, config, sign, verbose, arch } => {
             require_root()?;
             Logger::section("BUILDING LIVE ISO");
 
@@ -59,7 +114,7 @@ fn main() -> Result<()> {
                     std::process::exit(1);
                 }
 
-                Logger::info(&format!("Using custom config from: {}", cfg_path.cyan()));
+                Logger::info(&format!("Using custom config from: {}", cfg_path.if_supports_color(Stream::Stdout, |t| t.cyan())));
 
                 // Clean existing config to avoid mixing
                 if dest_path.exists() {
@@ -72,9 +127,17 @@ fn main() -> Result<()> {
                 run_command("cp", &["-r", cfg_path.as_str(), "config"], "Copy Config")?;
             }
 
+            let mut lb_config_args = vec!["config"];
+            if let Some(arch) = arch {
+                lb_config_args.extend(["--architecture", arch.name()]);
+            }
+
             if !Path::new("config").exists() {
                 Logger::warn("No ./config directory found. Running default 'lb config'...");
-                run_command("lb", &["config"], "Default Config")?;
+                run_command("lb", &lb_config_args, "Default Config")?;
+            } else if let Some(arch) = arch {
+                Logger::info(&format!("Setting architecture to {}", arch.name().if_supports_color(Stream::Stdout, |t| t.cyan())));
+                run_command("lb", &lb_config_args, "Set Architecture")?;
             }
 
             // 2. Clean previous build artifacts
@@ -85,16 +148,9 @@ fn main() -> Result<()> {
             // 3. Build
             Logger::info("Starting build process. This may take a long time...");
             let build_start = std::time::Instant::now();
+            let build_started_at = std::time::SystemTime::now();
             
-            // Run lb build
-            // streaming output to stdout so user sees progress of apt/bootstrap
-            let status = std::process::Command::new("lb")
-                .arg("build")
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .status()?;
-
-            if !status.success() {
+            if !run_lb_build(verbose)? {
                 Logger::error("Live Build failed.");
                 std::process::exit(1);
             }
@@ -103,31 +159,87 @@ fn main() -> Result<()> {
             let duration = build_start.elapsed();
             Logger::info(&format!("Build finished in {:.2?}.", duration));
 
-            // live-build usually outputs live-image-amd64.hybrid.iso (depends on arch)
-            // We look for any .iso file created recently or specific names
-            let possible_names = vec![
-                "live-image-amd64.hybrid.iso",
-                "live-image-amd64.iso",
-                "live-image-i386.hybrid.iso"
-            ];
+            // live-build outputs live-image-<arch>.hybrid.iso, or .iso where
+            // hybrid images are not supported (e.g. arm64)
+            let archs = match arch {
+                Some(arch) => vec![arch],
+                None => Arch::ALL.to_vec(),
+            };
+            let possible_names: Vec<String> = archs
+                .iter()
+                .flat_map(|a| [format!("live-image-{}.hybrid.iso", a.name()), format!("live-image-{}.iso", a.name())])
+                .collect();
+
+            let detected = possible_names
+                .iter()
+                .map(PathBuf::from)
+                .find(|p| p.exists())
+                .or_else(|| newest_iso_since(Path::new("."), build_started_at));
 
             let mut found = false;
-            for name in possible_names {
-                if Path::new(name).exists() {
-                    run_command("mv", &[name, &output], "Rename ISO")?;
-                    found = true;
-                    break;
+            if let Some(iso) = detected {
+                let iso = iso.to_string_lossy().to_string();
+                if iso.trim_start_matches("./") != output {
+                    run_command("mv", &[iso.as_str(), &output], "Rename ISO")?;
                 }
+                found = true;
             }
 
             if found {
-                Logger::success(&format!("ISO generated successfully: {}", output.green().bold()));
+                Logger::success(&format!("ISO generated successfully: {}", output.if_supports_color(Stream::Stdout, |t| t.green().bold())));
+
+                let checksum = write_checksum(&output)?;
+                Logger::info(&format!("SHA-256: {} ({}.sha256)", checksum.if_supports_color(Stream::Stdout, |t| t.cyan()), output));
+
+                if let Some(key) = sign {
+                    let signature = format!("{}.sig", output);
+                    run_command("gpg", &[
+                        "--batch", "--yes",
+                        "--local-user", &key,
+                        "--output", &signature,
+                        "--detach-sign", &output
+                    ], "Sign ISO")?;
+                    Logger::success(&format!("Signature written to {}", signature));
+                }
             } else {
                 Logger::warn("Build command succeeded, but could not auto-detect output ISO to rename.");
                 Logger::warn("Check the current directory for the generated file.");
             }
             Logger::end_section();
         }
+        Commands::Clean { yes } => {
+            require_root()?;
+            Logger::section("CLEANING BUILD DIRECTORY");
+
+            if !yes && !Confirm::new()
+                .with_prompt("Purge live-build state and remove built ISOs in this directory?")
+                .interact()?
+            {
+                Logger::info("Aborted.");
+                return Ok(());
+            }
+
+            let before = calculate_dir_size(Path::new("."))?;
+
+            let spinner = create_spinner("Running lb clean --purge...");
+            run_command("lb", &["clean", "--purge"], "Live Build Purge")?;
+            spinner.finish_with_message("Live-build state purged.");
+
+            for entry in fs::read_dir(".")?.filter_map(|e| e.ok()) {
+                let path = entry.path();
+                let is_artifact = path
+                    .extension()
+                    .is_some_and(|ext| ext == "iso" || ext == "sha256" || ext == "sig");
+                if is_artifact && path.is_file() {
+                    fs::remove_file(&path)?;
+                    Logger::info(&format!("Removed {}", path.display()));
+                }
+            }
+
+            let after = calculate_dir_size(Path::new("."))?;
+            Logger::success(&format!("Reclaimed {}", format_size(before.saturating_sub(after))));
+            Logger::end_section();
+        }
         Commands::Delta { repo } => {
             Logger::info(&format!("Generating static deltas for repo: {}", repo));
             
@@ -149,11 +261,136 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Newest `*.iso` in `dir` modified at or after `since`, for live-build
+/// configurations that name their image differently.
+fn newest_iso_since(dir: &Path, since: std::time::SystemTime) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "iso"))
+        .filter_map(|p| Some((fs::metadata(&p).ok()?.modified().ok()?, p)))
+        .filter(|(modified, _)| *modified >= since)
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, p)| p)
+}
+
+/// Runs `lb build`. Verbose mode streams its output as-is, otherwise the output
+/// goes to build.log and a progress bar advances on each stage boundary.
+fn run_lb_build(verbose: bool) -> Result<bool> {
+    if verbose {
+        let status = Command::new("lb")
+            .arg("build")
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()?;
+        return Ok(status.success());
+    }
+
+    let mut child = Command::new("lb")
+        .arg("build")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Merge stdout and stderr line by line
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().map(|s| Box::new(s) as Box<dyn std::io::Read + Send>);
+    let stderr = child.stderr.take().map(|s| Box::new(s) as Box<dyn std::io::Read + Send>);
+    for stream in [stdout, stderr].into_iter().flatten() {
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stream).lines().map_while(|l| l.ok()) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let mut log = fs::File::create(BUILD_LOG)?;
+    let mut tail = VecDeque::with_capacity(20);
+    let pb = create_progress_bar(LB_STAGES.len() as u64, "Starting live-build...");
+
+    for line in rx {
+        writeln!(log, "{}", line)?;
+        if let Some(stage) = lb_stage(&line) {
+            if stage as u64 + 1 > pb.position() {
+                pb.set_position(stage as u64 + 1);
+                pb.set_message(LB_STAGES[stage].1);
+            }
+        }
+        if tail.len() == 20 {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        pb.set_position(LB_STAGES.len() as u64);
+        pb.finish_with_message("Live build complete.");
+    } else {
+        pb.abandon_with_message("Live build failed.");
+        for line in &tail {
+            eprintln!("   {}", line.if_supports_color(Stream::Stderr, |t| t.bright_black()));
+        }
+        Logger::info(&format!("Full output is in {}", BUILD_LOG));
+    }
+    Ok(status.success())
+}
+
+/// Index into `LB_STAGES` when `line` announces a top-level stage, which
+/// live-build prints as `[timestamp] lb <stage> ...`.
+fn lb_stage(line: &str) -> Option<usize> {
+    let mut tokens = line.split_whitespace();
+    tokens.find(|t| *t == "lb")?;
+    let stage = tokens.next()?;
+    LB_STAGES.iter().position(|(name, _)| *name == stage)
+}
+
+/// Hashes `path` and writes `<path>.sha256` in `sha256sum` format, so it can be
+/// checked with `sha256sum -c`. Returns the hex digest.
+fn write_checksum(path: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = fs::File::open(path)?;
+    std::io::copy(&mut file, &mut hasher)?;
+    let digest = hex::encode(hasher.finalize());
+
+    let file_name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| path.to_string());
+    fs::write(format!("{}.sha256", path), format!("{}  {}\n", digest, file_name))?;
+    Ok(digest)
+}
+
 fn require_root() -> Result<()> {
     if !Uid::current().is_root() {
-        Logger::error("Permission denied. Building a live image requires root privileges.");
-        Logger::info(&format!("Try: sudo hammer-builder build ..."));
+        Logger::error("Permission denied. live-build requires root privileges.");
+        Logger::info("Try: sudo hammer-builder <command> ...");
         std::process::exit(1);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn newest_iso_since_skips_older_and_other_files() {
+        let dir = std::env::temp_dir().join(format!("hammer-builder-iso-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let start = SystemTime::now() - Duration::from_secs(60);
+        for (name, age) in [("previous.iso", 3600), ("live-image-amd64.hybrid.iso", 30), ("build.log", 0), ("other.iso", 50)] {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+        }
+
+        assert_eq!(newest_iso_since(&dir, start), Some(dir.join("live-image-amd64.hybrid.iso")));
+        assert_eq!(newest_iso_since(&dir, SystemTime::now() + Duration::from_secs(60)), None);
+        assert_eq!(newest_iso_since(&dir.join("missing"), start), None);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -2,18 +2,65 @@ use miette::{IntoDiagnostic, Result};
 use hammer_core::Logger;
 use lexopt::{Arg, Parser, ValueExt};
 use nix::unistd::Uid;
-use owo_colors::OwoColorize;
+use owo_colors::{OwoColorize, Stream::Stdout};
 use std::env;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 const BIN_DIR: &str = "/usr/lib/HackerOS/hammer/bin";
 
+/// Subcommands and the words that may follow them (long flags, or the
+/// subcommands of hammer-read), used to generate shell completions.
+/// Keep in sync with the dispatch table in `main`.
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("install", &["--distro", "--type", "--bin", "--remove-on-failure", "--gpu", "--audio", "--box"]),
+    ("search", &["--limit", "--box"]),
+    ("remove-app", &["--distro", "--box"]),
+    ("list-apps", &["--all", "--box"]),
+    ("app-logs", &["--debug-run", "--follow", "--box"]),
+    ("update", &[
+        "--vacuum-logs", "--include", "--exclude", "--allow-insecure-repos",
+        "--skip-space-check", "--space-headroom", "--timeout", "--dry-run", "--force",
+    ]),
+    ("layer", &["--dry-run", "--force"]),
+    ("clean", &["--logs", "--keep-days", "--keep", "--dry-run"]),
+    ("rollback", &["--yes", "--confirm-reboot-safe"]),
+    ("set-suite", &["--i-know-what-im-doing"]),
+    ("diff", &[]),
+    ("diff-config", &[]),
+    ("prune", &["--older-than", "--keep-min"]),
+    ("status", &["--json"]),
+    ("snapshot", &["--name", "--description", "--dry-run"]),
+    ("history", &[]),
+    ("doctor", &[]),
+    ("verify", &[]),
+    ("pin", &[]),
+    ("unpin", &[]),
+    ("snapshots", &["--json"]),
+    ("vacuum-logs", &[]),
+    ("snapshot-boot", &[]),
+    ("read-only", READ_ONLY_COMMANDS),
+    ("ro", READ_ONLY_COMMANDS),
+    ("completions", &["bash", "zsh", "fish", "elvish"]),
+    ("help", &[]),
+    ("version", &[]),
+];
+
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "lock", "unlock", "temporary-unlock", "temporary-lock", "overlay-drop", "overlay-commit", "install", "status",
+];
+
 fn main() -> Result<()> {
     Logger::init()?;
 
-    let args: Vec<String> = env::args().collect();
-    let mut parser = Parser::from_env();
+    let mut args: Vec<String> = env::args().collect();
+    // --no-color may come anywhere; drop it and pass the choice on through NO_COLOR
+    if args.iter().any(|a| a == "--no-color") {
+        args.retain(|a| a != "--no-color");
+        env::set_var("NO_COLOR", "1");
+        Logger::set_color(false);
+    }
+    let mut parser = Parser::from_args(args[1..].iter().cloned());
 
     // Peek at the first argument to decide dispatch
     let arg = parser.next().into_diagnostic()?;
@@ -24,23 +71,43 @@ fn main() -> Result<()> {
             match command.as_str() {
                 // CONTAINER APPS
                 "install" => run_binary("hammer-containers", &["install"], &args[2..])?,
+                "search" => run_binary("hammer-containers", &["search"], &args[2..])?,
                 "remove-app" => run_binary("hammer-containers", &["remove"], &args[2..])?,
                 "list-apps" => run_binary("hammer-containers", &["list"], &args[2..])?,
+                "app-logs" => run_binary("hammer-containers", &["logs"], &args[2..])?,
 
                 // SYSTEM UPDATES
                 "update" => require_root(|| run_binary("hammer-updater", &["update"], &args[2..]))?,
                 "layer" => require_root(|| run_binary("hammer-updater", &["layer"], &args[2..]))?,
                 "clean" => require_root(|| run_binary("hammer-updater", &["clean"], &args[2..]))?,
                 "rollback" => require_root(|| run_binary("hammer-updater", &["rollback"], &args[2..]))?,
+                "set-suite" => require_root(|| run_binary("hammer-updater", &["set-suite"], &args[2..]))?,
+                "diff" => require_root(|| run_binary("hammer-updater", &["diff"], &args[2..]))?,
+                "diff-config" => require_root(|| run_binary("hammer-updater", &["diff-config"], &args[2..]))?,
+                "prune" => require_root(|| run_binary("hammer-updater", &["prune"], &args[2..]))?,
+                "status" => require_root(|| run_binary("hammer-updater", &["status"], &args[2..]))?,
+                "snapshot" => require_root(|| run_binary("hammer-updater", &["snapshot"], &args[2..]))?,
+                "doctor" => require_root(|| run_binary("hammer-updater", &["doctor"], &args[2..]))?,
+                "verify" => require_root(|| run_binary("hammer-updater", &["verify"], &args[2..]))?,
+                "history" => require_root(|| run_binary("hammer-updater", &["history"], &args[2..]))?,
+                "pin" => require_root(|| run_binary("hammer-updater", &["pin"], &args[2..]))?,
+                "unpin" => require_root(|| run_binary("hammer-updater", &["unpin"], &args[2..]))?,
+                "snapshots" => require_root(|| run_binary("hammer-updater", &["list"], &args[2..]))?,
+                "vacuum-logs" => require_root(|| run_binary("hammer-updater", &["vacuum-logs"], &args[2..]))?,
+                "snapshot-boot" => require_root(|| run_binary("hammer-updater", &["snapshot-boot"], &args[2..]))?,
                 
                 // UTILS
+                "read-only" | "ro" if args.get(2).map(String::as_str) == Some("status") => {
+                    run_binary("hammer-read", &[], &args[2..])?
+                }
                 "read-only" | "ro" => require_root(|| run_binary("hammer-read", &[], &args[2..]))?,
                 
+                "completions" => print_completions(&args[2..])?,
                 "help" => print_help(),
                 "version" => print_version(),
                 _ => {
                      print_help();
-                     println!("\n{}", format!("   ERROR: Unknown command '{}'", command).black().on_red());
+                     println!("\n{}", format!("   ERROR: Unknown command '{}'", command).if_supports_color(Stdout, |t| t.black().on_red()));
                      std::process::exit(1);
                 }
             }
@@ -58,8 +125,8 @@ fn require_root<F>(f: F) -> Result<()>
 where F: FnOnce() -> Result<()> 
 {
     if !Uid::current().is_root() {
-        println!("{}", " ACCESS DENIED: Root privileges required.".red().bold());
-        println!(" Run with: {}", "sudo hammer <command>".yellow());
+        println!("{}", " ACCESS DENIED: Root privileges required.".if_supports_color(Stdout, |t| t.red().bold()));
+        println!(" Run with: {}", "sudo hammer <command>".if_supports_color(Stdout, |t| t.yellow()));
         std::process::exit(1);
     }
     f()
@@ -135,32 +202,131 @@ fn print_help() {
             @%@%++=#@@@*++*%@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@*++*@@@#+++%%%@            
            @%%%%%%%%@@@%%%%@@%@ @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@ %%@@%%%%@@@@%%%%%%%@           
             @%@@@@@@@%@@@@@@@@  @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@  %@@@@@@@@@@@@@@@%%            
-"#.cyan().bold());
+"#.if_supports_color(Stdout, |t| t.cyan().bold()));
    
-   println!("   {}", "NEXT-GEN SYSTEM MANAGER".black().on_magenta());
+   println!("   {}", "NEXT-GEN SYSTEM MANAGER".if_supports_color(Stdout, |t| t.black().on_magenta()));
    println!("   Atomic Updates - Btrfs Snapshots - Isolated Apps\n");
 
     let print_cmd = |cmd: &str, desc: &str| {
-        println!("   {: <20} {}", cmd.green().bold(), desc.bright_black());
+        println!("   {: <20} {}", cmd.if_supports_color(Stdout, |t| t.green().bold()), desc.if_supports_color(Stdout, |t| t.bright_black()));
     };
 
-    println!("{}", " APPLICATIONS".yellow().bold());
+    println!("{}", " APPLICATIONS".if_supports_color(Stdout, |t| t.yellow().bold()));
     print_cmd("install <pkg>", "Install CLI/GUI app in container");
+    print_cmd("  --distro <name>", "Container distro: debian, fedora, arch, ubuntu");
+    print_cmd("  --type cli|gui --bin <name>", "Skip the install prompts");
+    print_cmd("  --gpu / --audio", "Share the GPU or sound server with the app");
+    print_cmd("  --box <name>", "Use the box hammer-box-<name> (any app command)");
+    print_cmd("search <query>", "Search packages available to the container");
     print_cmd("remove-app <pkg>", "Remove installed app wrapper");
-    print_cmd("list-apps", "List all containerized apps");
+    print_cmd("list-apps [--all]", "List containerized apps (--all: every box)");
+    print_cmd("app-logs <pkg>", "Run an app and show its output");
 
-    println!("\n{}", " SYSTEM & UPDATES".blue().bold());
+    println!("\n{}", " SYSTEM & UPDATES".if_supports_color(Stdout, |t| t.blue().bold()));
     print_cmd("update", "Atomic system update (Snapshot -> Update)");
+    print_cmd("  --include <pkg>", "Also install a package during this update");
+    print_cmd("  --exclude <pkg>", "Hold a package back during this update");
+    print_cmd("  --allow-insecure-repos", "Update despite unsigned APT sources");
+    print_cmd("  --skip-space-check", "Update without checking free disk space");
+    print_cmd("  --timeout <secs>", "Abort the package upgrade after this long");
     print_cmd("layer <pkg>", "Install package on host via snapshot");
-    print_cmd("rollback", "Revert system to previous state");
-    print_cmd("clean", "Prune old snapshots");
+    print_cmd("set-suite <suite>", "Upgrade to another Debian release");
+    print_cmd("rollback [n]", "Revert system to previous state (n = steps back)");
+    print_cmd("  --yes", "Skip the confirmation (needed without a terminal)");
+    print_cmd("  --confirm-reboot-safe", "Roll back even if the kernel check fails");
+    print_cmd("status [--json]", "Show kernel, package set and update state");
+    print_cmd("snapshot", "Take a manual snapshot (--name, --description)");
+    print_cmd("doctor", "Check btrfs layout, lock, free space and /boot");
+    print_cmd("verify [snap]", "Re-check a snapshot's packages and kernel");
+    print_cmd("history", "Show the snapshot timeline up to the live system");
+    print_cmd("pin / unpin <snap>", "Keep a snapshot from clean and prune");
+    print_cmd("snapshots", "List snapshots and their metadata");
+    print_cmd("diff [a] [b]", "Show package changes between snapshots");
+    print_cmd("diff-config <a> [b]", "Compare APT sources between snapshots");
+    print_cmd("clean [--keep N]", "Delete all but the N newest snapshots (default 3)");
+    print_cmd("prune --older-than", "Delete snapshots older than e.g. 30d");
+    print_cmd("vacuum-logs", "Empty /var/log inside snapshots");
+    print_cmd("snapshot-boot", "Copy a separate /boot into snapshots");
+
+    println!("\n{}", " SECURITY".if_supports_color(Stdout, |t| t.red().bold()));
+    print_cmd("read-only | ro", "Manage file system locks (lock, unlock, temporary-unlock)");
+    print_cmd("read-only overlay-drop", "Discard the temporary /usr overlay");
+    print_cmd("read-only overlay-commit", "Keep the overlay's /usr changes (--dry-run)");
+    print_cmd("read-only status", "Show whether the protected paths are locked");
 
-    println!("\n{}", " SECURITY".red().bold());
-    print_cmd("read-only", "Manage file system locks");
+    println!("\n{}", " OPTIONS".if_supports_color(Stdout, |t| t.bright_black().bold()));
+    print_cmd("--no-color", "Plain output, same as NO_COLOR=1");
+    print_cmd("completions <shell>", "Print a bash, zsh, fish or elvish completion script");
     
     println!();
 }
 
+/// Writes a completion script for the given shell to stdout, e.g.
+/// `hammer completions bash > /etc/bash_completion.d/hammer`.
+fn print_completions(args: &[String]) -> Result<()> {
+    let names: Vec<&str> = COMMANDS.iter().map(|(name, _)| *name).collect();
+    match args.first().map(String::as_str) {
+        Some("bash") => {
+            println!("_hammer() {{");
+            println!("    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"");
+            println!("    if [ \"$COMP_CWORD\" -eq 1 ]; then");
+            println!("        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", names.join(" "));
+            println!("        return");
+            println!("    fi");
+            println!("    case \"${{COMP_WORDS[1]}}\" in");
+            for (name, flags) in COMMANDS.iter().filter(|(_, f)| !f.is_empty()) {
+                println!("        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;", name, flags.join(" "));
+            }
+            println!("    esac");
+            println!("}}");
+            println!("complete -F _hammer hammer");
+        }
+        Some("zsh") => {
+            println!("#compdef hammer");
+            println!("_hammer() {{");
+            println!("    if (( CURRENT == 2 )); then");
+            println!("        compadd -- {}", names.join(" "));
+            println!("        return");
+            println!("    fi");
+            println!("    case $words[2] in");
+            for (name, flags) in COMMANDS.iter().filter(|(_, f)| !f.is_empty()) {
+                println!("        {}) compadd -- {} ;;", name, flags.join(" "));
+            }
+            println!("    esac");
+            println!("}}");
+            println!("compdef _hammer hammer");
+        }
+        Some("fish") => {
+            println!("complete -c hammer -f");
+            for (name, flags) in COMMANDS {
+                println!("complete -c hammer -n __fish_use_subcommand -a {}", name);
+                for word in flags.iter() {
+                    match word.strip_prefix("--") {
+                        Some(flag) => println!("complete -c hammer -n '__fish_seen_subcommand_from {}' -l {}", name, flag),
+                        None => println!("complete -c hammer -n '__fish_seen_subcommand_from {}' -a {}", name, word),
+                    }
+                }
+            }
+        }
+        Some("elvish") => {
+            println!("set edit:completion:arg-completer[hammer] = {{|@words|");
+            println!("    if (== (count $words) 2) {{");
+            println!("        put {}", names.join(" "));
+            println!("        return");
+            println!("    }}");
+            for (name, flags) in COMMANDS.iter().filter(|(_, f)| !f.is_empty()) {
+                println!("    if (eq $words[1] {}) {{ put {} }}", name, flags.join(" "));
+            }
+            println!("}}");
+        }
+        _ => {
+            println!("Usage: hammer completions <bash|zsh|fish|elvish>");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
 fn print_version() {
     println!("hammer 1.1.0 (Btrfs @layout edition)");
 }
\ No newline at end of file
@@ -1,10 +1,17 @@
 use miette::{IntoDiagnostic, Result};
-use clap::{Parser, Subcommand};
-use hammer_core::{create_spinner, run_command, Logger};
-use owo_colors::OwoColorize;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use hammer_core::{
+    create_spinner, run_command, run_command_retry, HammerError, Logger, NETWORK_RETRIES,
+    NETWORK_RETRY_DELAY,
+};
+use owo_colors::{OwoColorize, Stream};
+use serde::{Deserialize, Serialize};
 use dialoguer::{Select, Input, Confirm};
+use nix::unistd::{Gid, Uid, User};
 use std::fs;
-use std::path::Path;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::os::unix::fs::PermissionsExt;
 
 #[derive(Parser)]
@@ -12,50 +19,443 @@ use std::os::unix::fs::PermissionsExt;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Work in the box hammer-box-NAME instead of hammer-box, creating it on demand
+    #[arg(long = "box", global = true, value_name = "NAME")]
+    box_name: Option<String>,
+}
+
+#[derive(Args)]
+struct InstallArgs {
+    package: String,
+
+    /// Distribution of the container (remembered once the container exists)
+    #[arg(long, value_enum)]
+    distro: Option<ContainerProfile>,
+
+    /// Kind of wrapper to create, skips the prompt
+    #[arg(long = "type", value_enum)]
+    app_type: Option<AppType>,
+
+    /// Command that launches the app, skips the prompt
+    #[arg(long = "bin", value_name = "NAME")]
+    bin_name: Option<String>,
+
+    /// Uninstall the package again if its wrapper cannot be created
+    #[arg(long)]
+    remove_on_failure: bool,
+
+    /// Give the app GPU access through /dev/dri
+    #[arg(long)]
+    gpu: bool,
+
+    /// Give the app the host's PulseAudio/PipeWire sockets
+    #[arg(long)]
+    audio: bool,
+}
+
+/// Host resources shared with the container. Devices and sockets can only be
+/// added when the container is created, so it gets the union of what any
+/// installed app asked for.
+#[derive(Clone, Copy, Default)]
+struct Passthrough {
+    gpu: bool,
+    audio: bool,
+}
+
+impl Passthrough {
+    /// What the apps in the manifest need that live in this run's box.
+    fn recorded() -> Self {
+        load_manifest()
+        .unwrap_or_default()
+        .iter()
+        .filter(|e| e.container == container())
+        .fold(Self::default(), |acc, e| Self { gpu: acc.gpu || e.gpu, audio: acc.audio || e.audio })
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self { gpu: self.gpu || other.gpu, audio: self.audio || other.audio }
+    }
+
+    /// Extra `podman run` arguments. Audio binds the invoking user's runtime
+    /// sockets to the same paths inside the container.
+    fn create_args(self, runtime_dir: &Path) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.gpu {
+            args.extend(["--device".to_string(), "/dev/dri".to_string()]);
+        }
+        if self.audio {
+            for socket in ["pulse", "pipewire-0"] {
+                let path = runtime_dir.join(socket);
+                if path.exists() {
+                    args.extend(["-v".to_string(), format!("{}:{}", path.display(), path.display())]);
+                }
+            }
+        }
+        args
+    }
+
+    /// Extra `podman exec` environment for the generated wrappers.
+    fn exec_env(self) -> &'static str {
+        if self.audio {
+            "-e PULSE_SERVER=unix:$XDG_RUNTIME_DIR/pulse/native "
+        } else {
+            ""
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AppType {
+    Cli,
+    Gui,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Install an application inside the hammer-box container
-    Install {
-        package: String,
-    },
+    /// Install an application inside the container
+    Install(InstallArgs),
     /// Remove an application wrapper
     Remove {
         package: String,
+
+        /// Distribution of the container (remembered once the container exists)
+        #[arg(long, value_enum)]
+        distro: Option<ContainerProfile>,
+    },
+    /// Search the container's package repositories
+    Search {
+        query: String,
+
+        /// Show at most this many results
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
     },
     /// List installed wrappers
-    List,
+    List {
+        /// List the wrappers and state of every hammer box
+        #[arg(long)]
+        all: bool,
+    },
+    /// Run an app in the container and show what it prints
+    Logs {
+        package: String,
+
+        /// Run attached to the terminal with toolkit debug output enabled
+        #[arg(long)]
+        debug_run: bool,
+
+        /// Stream the app's output as it runs instead of printing it on exit
+        #[arg(long, conflicts_with = "debug_run")]
+        follow: bool,
+    },
 }
 
 const CONTAINER_NAME: &str = "hammer-box";
-const CONTAINER_IMAGE: &str = "docker.io/library/debian:bookworm";
-const WRAPPER_DIR: &str = "/usr/local/bin";
-const DESKTOP_DIR: &str = "/usr/share/applications";
+/// Remembers which profile each box was created with, relative to the home
+const PROFILE_DIR: &str = ".local/share/hammer/containers";
+/// Directory of the wrapper manifest, relative to the home
+const MANIFEST_DIR: &str = ".local/share/hammer";
+const MANIFEST_FILE: &str = "containers.json";
+/// Relative to the invoking user's home, the container belongs to that user
+const WRAPPER_DIR: &str = ".local/bin";
+const DESKTOP_DIR: &str = ".local/share/applications";
+
+/// The box this run works in, set once from `--box`
+static CONTAINER: OnceLock<String> = OnceLock::new();
+
+/// `hammer-box`, or `hammer-box-<name>` when `--box <name>` was given.
+fn container() -> &'static str {
+    CONTAINER.get().map(String::as_str).unwrap_or(CONTAINER_NAME)
+}
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    if let Some(name) = cli.box_name {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+            return Err(HammerError::ConfigError(format!(
+                "Invalid box name '{}', use letters, digits, '-', '_' or '.'", name
+            )).into());
+        }
+        let _ = CONTAINER.set(format!("{}-{}", CONTAINER_NAME, name));
+    }
+
     match cli.command {
-        Commands::Install { package } => handle_install(package)?,
-        Commands::Remove { package } => handle_remove(package)?,
-        Commands::List => handle_list()?,
+        Commands::Install(args) => handle_install(args)?,
+        Commands::Remove { package, distro } => handle_remove(package, distro)?,
+        Commands::Search { query, limit } => handle_search(&query, limit)?,
+        Commands::List { all } => handle_list(all)?,
+        Commands::Logs { package, debug_run, follow } => handle_logs(package, debug_run, follow)?,
     }
 
     Ok(())
 }
 
-fn ensure_container_exists() -> Result<()> {
-    let output = run_command("podman", &["ps", "-a", "--format", "{{.Names}}"], "Check Container")?;
+/// Distribution a hammer box runs, with its image and package manager.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ContainerProfile {
+    Debian,
+    Fedora,
+    Arch,
+    Ubuntu,
+}
+
+impl ContainerProfile {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Debian => "debian",
+            Self::Fedora => "fedora",
+            Self::Arch => "arch",
+            Self::Ubuntu => "ubuntu",
+        }
+    }
+
+    fn image(self) -> &'static str {
+        match self {
+            Self::Debian => "docker.io/library/debian:bookworm",
+            Self::Fedora => "registry.fedoraproject.org/fedora:latest",
+            Self::Arch => "docker.io/library/archlinux:latest",
+            Self::Ubuntu => "docker.io/library/ubuntu:latest",
+        }
+    }
+
+    /// Refreshes the package index after the container is created
+    fn refresh_cmd(self) -> &'static [&'static str] {
+        match self {
+            Self::Debian | Self::Ubuntu => &["apt-get", "update"],
+            Self::Fedora => &["dnf", "makecache"],
+            Self::Arch => &["pacman", "-Sy"],
+        }
+    }
+
+    fn install_cmd(self) -> &'static [&'static str] {
+        match self {
+            Self::Debian | Self::Ubuntu => &["apt-get", "install", "-y"],
+            Self::Fedora => &["dnf", "install", "-y"],
+            Self::Arch => &["pacman", "-S", "--noconfirm"],
+        }
+    }
+
+    fn search_cmd(self) -> &'static [&'static str] {
+        match self {
+            Self::Debian | Self::Ubuntu => &["apt-cache", "search"],
+            Self::Fedora => &["dnf", "search", "-q"],
+            Self::Arch => &["pacman", "-Ss"],
+        }
+    }
+
+    fn remove_cmd(self) -> &'static [&'static str] {
+        match self {
+            Self::Debian | Self::Ubuntu => &["apt-get", "remove", "-y"],
+            Self::Fedora => &["dnf", "remove", "-y"],
+            Self::Arch => &["pacman", "-R", "--noconfirm"],
+        }
+    }
+
+    /// The profile the current box was created with, if it was recorded.
+    fn stored() -> Option<Self> {
+        let path = InvokingUser::get().ok()?.home.join(PROFILE_DIR).join(container());
+        let name = fs::read_to_string(path).ok()?;
+        Self::from_str(name.trim(), true).ok()
+    }
+
+    fn store(self) -> Result<()> {
+        let user = InvokingUser::get()?;
+        let path = user.dir(PROFILE_DIR)?.join(container());
+        fs::write(&path, self.name()).into_diagnostic()?;
+        user.chown(&path)
+    }
+
+    /// Picks `requested`, falling back to the stored profile and then Debian.
+    /// A request that contradicts the existing container is refused.
+    fn resolve(requested: Option<Self>) -> Result<Self> {
+        match (requested, Self::stored()) {
+            (Some(req), Some(stored)) if req != stored => Err(HammerError::ConfigError(format!(
+                "{} was created as {}. Remove it with 'podman rm -f {}' to switch to {}.",
+                container(), stored.name(), container(), req.name()
+            )).into()),
+            (Some(req), _) => Ok(req),
+            (None, stored) => Ok(stored.unwrap_or(Self::Debian)),
+        }
+    }
+}
+
+/// The user hammer acts for: `$SUDO_USER` when run through sudo, the caller otherwise.
+struct InvokingUser {
+    home: PathBuf,
+    uid: Uid,
+    gid: Gid,
+}
+
+impl InvokingUser {
+    fn get() -> Result<Self> {
+        let sudo_user = std::env::var("SUDO_USER")
+        .ok()
+        .and_then(|name| User::from_name(&name).ok().flatten());
+        if let Some(user) = sudo_user {
+            return Ok(Self { home: user.dir, uid: user.uid, gid: user.gid });
+        }
+
+        let (uid, gid) = (Uid::current(), Gid::current());
+        let home = match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home),
+            None => User::from_uid(uid)
+            .into_diagnostic()?
+            .map(|user| user.dir)
+            .ok_or_else(|| HammerError::ConfigError(format!("Cannot determine the home directory of uid {}", uid)))?,
+        };
+        Ok(Self { home, uid, gid })
+    }
+
+    /// `rel` under the user's home, creating missing parts owned by the user.
+    fn dir(&self, rel: &str) -> Result<PathBuf> {
+        let mut path = self.home.clone();
+        for part in Path::new(rel).components() {
+            path.push(part);
+            if !path.exists() {
+                fs::create_dir(&path).into_diagnostic()?;
+                self.chown(&path)?;
+            }
+        }
+        Ok(path)
+    }
+
+    fn chown(&self, path: &Path) -> Result<()> {
+        nix::unistd::chown(path, Some(self.uid), Some(self.gid)).into_diagnostic()
+    }
+}
+
+/// One installed wrapper as recorded in the manifest.
+#[derive(Serialize, Deserialize)]
+struct WrapperEntry {
+    name: String,
+    #[serde(rename = "type")]
+    app_type: AppType,
+    inner_cmd: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    desktop_file: Option<PathBuf>,
+    #[serde(default)]
+    gpu: bool,
+    #[serde(default)]
+    audio: bool,
+    /// Box the app is installed in; older manifests only knew hammer-box
+    #[serde(default = "default_container")]
+    container: String,
+}
+
+fn default_container() -> String {
+    CONTAINER_NAME.to_string()
+}
+
+/// Reads the wrapper manifest, `None` when it was never written.
+fn load_manifest() -> Option<Vec<WrapperEntry>> {
+    let path = InvokingUser::get().ok()?.home.join(MANIFEST_DIR).join(MANIFEST_FILE);
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Applies `update` to the manifest and writes it back.
+fn update_manifest<F: FnOnce(&mut Vec<WrapperEntry>)>(update: F) -> Result<()> {
+    let user = InvokingUser::get()?;
+    let mut entries = load_manifest().unwrap_or_default();
+    update(&mut entries);
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let path = user.dir(MANIFEST_DIR)?.join(MANIFEST_FILE);
+    let json = serde_json::to_string_pretty(&entries).into_diagnostic()?;
+    fs::write(&path, json).into_diagnostic()?;
+    user.chown(&path)
+}
 
-    if !output.contains(CONTAINER_NAME) {
-        Logger::info("Initializing hammer-box container environment...");
+fn record_wrapper(entry: WrapperEntry) -> Result<()> {
+    let taken = load_manifest()
+    .unwrap_or_default()
+    .into_iter()
+    .find(|e| e.name == entry.name && e.container != entry.container);
+    if let Some(other) = taken {
+        Logger::warn(&format!("{} pointed into {}, it now runs from {}.", entry.name, other.container, entry.container));
+    }
+    update_manifest(|entries| {
+        entries.retain(|e| e.name != entry.name);
+        entries.push(entry);
+    })
+}
+
+/// The command line that starts podman. Under sudo podman runs as the invoking
+/// user, so the box lives in the user's rootless podman, the one the wrappers
+/// in ~/.local/bin talk to, rather than in root's.
+fn podman_argv() -> &'static [String] {
+    static ARGV: OnceLock<Vec<String>> = OnceLock::new();
+    ARGV.get_or_init(|| {
+        let sudo_user = std::env::var("SUDO_USER")
+        .ok()
+        .filter(|_| Uid::effective().is_root())
+        .and_then(|name| User::from_name(&name).ok().flatten())
+        .filter(|user| !user.uid.is_root());
+        match sudo_user {
+            Some(user) => vec![
+                "runuser".to_string(), "-u".to_string(), user.name, "--".to_string(),
+                "env".to_string(), format!("XDG_RUNTIME_DIR=/run/user/{}", user.uid), "podman".to_string(),
+            ],
+            None => vec!["podman".to_string()],
+        }
+    })
+}
+
+fn podman_args<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    podman_argv()[1..].iter().map(String::as_str).chain(args.iter().copied()).collect()
+}
+
+fn podman(args: &[&str], description: &str) -> Result<String> {
+    run_command(&podman_argv()[0], &podman_args(args), description)
+}
+
+fn podman_retry(args: &[&str], description: &str) -> Result<String> {
+    run_command_retry(&podman_argv()[0], &podman_args(args), description, NETWORK_RETRIES, NETWORK_RETRY_DELAY)
+}
+
+/// `podman` for commands that need their own stdio.
+fn podman_command() -> std::process::Command {
+    let mut command = std::process::Command::new(&podman_argv()[0]);
+    command.args(&podman_argv()[1..]);
+    command
+}
+
+/// Podman's state for the current box (`running`, `exited`, ...), `None` if
+/// there is no such container.
+fn container_state() -> Result<Option<String>> {
+    let output = podman(&["ps", "-a", "--format", "{{.Names}}\t{{.State}}"], "Check Container")?;
+    Ok(find_state(&output, container()))
+}
+
+/// Looks `name` up in `podman ps --format '{{.Names}}\t{{.State}}'` output.
+/// Matches whole names, a substring check also hits e.g. "hammer-box-old".
+fn find_state(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (found, state) = line.split_once('\t').unwrap_or((line, ""));
+        (found.trim() == name).then(|| state.trim().to_lowercase())
+    })
+}
+
+fn ensure_container_exists(profile: ContainerProfile, passthrough: Passthrough) -> Result<()> {
+    let state = container_state()?;
+
+    if state.is_none() {
+        Logger::info(&format!("Initializing {} container environment...", container()));
         let spinner = create_spinner("Pulling base image & Creating container...");
 
+        podman_retry(&["pull", profile.image()], "Pull Image")
+        .map_err(|e| HammerError::CommandFailed(format!("Base image {} is not available: {}", profile.image(), e)))?;
+
+        let runtime_dir = PathBuf::from(format!("/run/user/{}", InvokingUser::get()?.uid));
+        let extra = passthrough.create_args(&runtime_dir);
+
         // Create an infinite loop container that we can exec into
-        run_command("podman", &[
+        let mut create = vec![
             "run", "-d",
-            "--name", CONTAINER_NAME,
+            "--name", container(),
             "--restart", "always",
             // Share networking and X11 for GUI apps
             "--net=host",
@@ -63,96 +463,201 @@ fn ensure_container_exists() -> Result<()> {
             "-e", "DISPLAY",
             "-e", "WAYLAND_DISPLAY",
             "-e", "XDG_RUNTIME_DIR",
-            CONTAINER_IMAGE,
-            "sleep", "infinity"
-        ], "Create Container")?;
+        ];
+        create.extend(extra.iter().map(|a| a.as_str()));
+        create.extend([profile.image(), "sleep", "infinity"]);
+        podman(&create, "Create Container")?;
+        profile.store()?;
 
-        // Update apt inside
-        run_command("podman", &["exec", CONTAINER_NAME, "apt-get", "update"], "Update Container APT")?;
+        // Refresh the package index inside
+        let mut refresh = vec!["exec", container()];
+        refresh.extend(profile.refresh_cmd());
+        podman_retry(&refresh, "Refresh Container Packages")?;
 
         spinner.finish_with_message("Container environment ready.");
     } else {
-        // Ensure it's running
-        run_command("podman", &["start", CONTAINER_NAME], "Start Container")?;
+        let state = state.unwrap_or_default();
+        if state != "running" {
+            Logger::info(&format!("{} is {}, starting it...", container(), state));
+            podman(&["start", container()], "Start Container").map_err(|e| {
+                HammerError::CommandFailed(format!(
+                    "{} is {} and could not be started: {}. Recreate it with 'podman rm -f {}' and reinstall.",
+                    container(), state, e, container()
+                ))
+            })?;
+        }
+
+        let config = podman(
+            &["inspect", "--format", "{{json .HostConfig.Devices}} {{json .Mounts}}", container()],
+            "Inspect Container",
+        )?;
+        let missing: Vec<&str> = [
+            (passthrough.gpu && !config.contains("/dev/dri"), "GPU"),
+            (passthrough.audio && !config.contains("pulse") && !config.contains("pipewire"), "audio"),
+        ]
+        .iter()
+        .filter(|(lacking, _)| *lacking)
+        .map(|(_, name)| *name)
+        .collect();
+        if !missing.is_empty() {
+            Logger::warn(&format!(
+                "{} was created without {} access. Recreate it with 'podman rm -f {}' and reinstall to enable it.",
+                container(), missing.join(" and "), container()
+            ));
+        }
     }
     Ok(())
 }
 
-fn handle_install(package: String) -> Result<()> {
-    ensure_container_exists()?;
+fn handle_install(args: InstallArgs) -> Result<()> {
+    let package = args.package;
+    let interactive = std::io::stdin().is_terminal();
+    let profile = ContainerProfile::resolve(args.distro)?;
+    let passthrough = Passthrough { gpu: args.gpu, audio: args.audio };
+    ensure_container_exists(profile, passthrough.union(Passthrough::recorded()))?;
 
-    Logger::info(&format!("Installing {} in container...", package.cyan()));
+    Logger::info(&format!("Installing {} in {} container...", package.if_supports_color(Stream::Stdout, |t| t.cyan()), profile.name()));
 
     // Install in container
-    let status = std::process::Command::new("podman")
-    .args(&["exec", "-it", CONTAINER_NAME, "apt-get", "install", "-y", &package])
-    .status()
-    .into_diagnostic()?;
+    let install = || {
+        podman_command()
+        .args(["exec", if interactive { "-it" } else { "-i" }, container()])
+        .args(profile.install_cmd())
+        .arg(&package)
+        .status()
+        .into_diagnostic()
+    };
+    let mut status = install()?;
+
+    // The container may have been stopped or removed under us; bring it back and try once more
+    if !status.success() && container_state()?.as_deref() != Some("running") {
+        Logger::warn(&format!("{} went away during the install, restoring it and retrying.", container()));
+        ensure_container_exists(profile, passthrough.union(Passthrough::recorded()))?;
+        status = install()?;
+    }
 
     if !status.success() {
-        Logger::error("Failed to install package in container.");
-        return Ok(());
+        return Err(HammerError::CommandFailed(format!("Installing {} in the container failed", package)).into());
+    }
+
+    if !interactive && (args.app_type.is_none() || args.bin_name.is_none()) {
+        Logger::warn("No terminal to prompt on, defaulting missing --type to cli and --bin to the package name.");
     }
 
     // Determine App Type
-    let types = vec!["CLI (Command Line Tool)", "GUI (Desktop Application)"];
-    let selection = Select::new()
-    .with_prompt("What type of application is this?")
-    .items(&types)
-    .default(0)
-    .interact()
-    .into_diagnostic()?;
+    let app_type = match args.app_type {
+        Some(app_type) => app_type,
+        None if !interactive => AppType::Cli,
+        None => {
+            let types = vec!["CLI (Command Line Tool)", "GUI (Desktop Application)"];
+            let selection = Select::new()
+            .with_prompt("What type of application is this?")
+            .items(&types)
+            .default(0)
+            .interact()
+            .into_diagnostic()?;
+            if selection == 0 { AppType::Cli } else { AppType::Gui }
+        }
+    };
+
+    let bin_name: String = match args.bin_name {
+        Some(bin_name) => bin_name,
+        None if !interactive => package.clone(),
+        None => Input::new()
+        .with_prompt("Enter the command name to launch it (e.g. alacritty)")
+        .with_initial_text(&package)
+        .interact_text()
+        .into_diagnostic()?,
+    };
+
+    let created = match app_type {
+        AppType::Cli => create_cli_wrapper(&bin_name, &bin_name, passthrough),
+        AppType::Gui => create_gui_wrapper(&bin_name, &bin_name, passthrough),
+    };
+
+    if let Err(err) = created {
+        Logger::error(&format!("Creating the wrapper for {} failed, cleaning up.", bin_name));
+        remove_wrapper_files(&bin_name);
+
+        let undo = args.remove_on_failure
+        || (interactive
+        && Confirm::new()
+        .with_prompt(format!("Uninstall {} from the container again?", package))
+        .interact()
+        .unwrap_or(false));
+        if undo {
+            remove_from_container(profile, &package)?;
+        } else {
+            Logger::warn(&format!("{} stays installed in the container.", package));
+        }
+        return Err(err);
+    }
 
-    let bin_name: String = Input::new()
-    .with_prompt("Enter the command name to launch it (e.g. alacritty)")
-    .with_initial_text(&package)
-    .interact_text()
-    .into_diagnostic()?;
+    Ok(())
+}
 
-    if selection == 0 {
-        // CLI
-        create_cli_wrapper(&bin_name, &bin_name)?;
-    } else {
-        // GUI
-        create_gui_wrapper(&bin_name, &bin_name)?;
-    }
+/// Best-effort removal of a half-written wrapper and its .desktop file.
+fn remove_wrapper_files(wrapper_name: &str) {
+    let Ok(user) = InvokingUser::get() else { return };
+    let _ = fs::remove_file(user.home.join(WRAPPER_DIR).join(wrapper_name));
+    let _ = fs::remove_file(user.home.join(DESKTOP_DIR).join(format!("{}.desktop", wrapper_name)));
+}
 
+fn remove_from_container(profile: ContainerProfile, package: &str) -> Result<()> {
+    let mut remove = vec!["exec", container()];
+    remove.extend(profile.remove_cmd());
+    remove.push(package);
+    podman(&remove, "Remove Package")?;
     Ok(())
 }
 
-fn create_cli_wrapper(wrapper_name: &str, inner_cmd: &str) -> Result<()> {
-    let wrapper_path = Path::new(WRAPPER_DIR).join(wrapper_name);
+fn create_cli_wrapper(wrapper_name: &str, inner_cmd: &str, passthrough: Passthrough) -> Result<()> {
+    let user = InvokingUser::get()?;
+    let wrapper_path = user.dir(WRAPPER_DIR)?.join(wrapper_name);
 
     let content = format!(r#"#!/bin/bash
-    exec podman exec -it {} {} "$@"
-    "#, CONTAINER_NAME, inner_cmd);
+    exec podman exec -it {}{} {} "$@"
+    "#, passthrough.exec_env(), container(), inner_cmd);
 
     fs::write(&wrapper_path, content).into_diagnostic()?;
 
     let mut perms = fs::metadata(&wrapper_path).into_diagnostic()?.permissions();
     perms.set_mode(0o755);
     fs::set_permissions(&wrapper_path, perms).into_diagnostic()?;
+    user.chown(&wrapper_path)?;
+
+    record_wrapper(WrapperEntry {
+        name: wrapper_name.to_string(),
+        app_type: AppType::Cli,
+        inner_cmd: inner_cmd.to_string(),
+        desktop_file: None,
+        gpu: passthrough.gpu,
+        audio: passthrough.audio,
+        container: container().to_string(),
+    })?;
 
     Logger::success(&format!("CLI wrapper created at {}", wrapper_path.display()));
     Ok(())
 }
 
-fn create_gui_wrapper(wrapper_name: &str, inner_cmd: &str) -> Result<()> {
+fn create_gui_wrapper(wrapper_name: &str, inner_cmd: &str, passthrough: Passthrough) -> Result<()> {
     // 1. Create binary wrapper to launch it
-    let bin_wrapper_path = Path::new(WRAPPER_DIR).join(wrapper_name);
+    let user = InvokingUser::get()?;
+    let bin_wrapper_path = user.dir(WRAPPER_DIR)?.join(wrapper_name);
     let bin_content = format!(r#"#!/bin/bash
     # Pass X11/Wayland vars
     xhost +local:root > /dev/null 2>&1
-    exec podman exec -e DISPLAY=$DISPLAY -e XDG_RUNTIME_DIR=$XDG_RUNTIME_DIR {} {} "$@"
-    "#, CONTAINER_NAME, inner_cmd);
+    exec podman exec -e DISPLAY=$DISPLAY -e XDG_RUNTIME_DIR=$XDG_RUNTIME_DIR {}{} {} "$@"
+    "#, passthrough.exec_env(), container(), inner_cmd);
 
     fs::write(&bin_wrapper_path, bin_content).into_diagnostic()?;
     let mut perms = fs::metadata(&bin_wrapper_path).into_diagnostic()?.permissions();
     perms.set_mode(0o755);
     fs::set_permissions(&bin_wrapper_path, perms).into_diagnostic()?;
+    user.chown(&bin_wrapper_path)?;
 
     // 2. Create .desktop file
-    let desktop_path = Path::new(DESKTOP_DIR).join(format!("{}.desktop", wrapper_name));
+    let desktop_path = user.dir(DESKTOP_DIR)?.join(format!("{}.desktop", wrapper_name));
     let desktop_content = format!(r#"[Desktop Entry]
     Name={} (Container)
     Exec={}
@@ -162,44 +667,224 @@ fn create_gui_wrapper(wrapper_name: &str, inner_cmd: &str) -> Result<()> {
     "#, wrapper_name, bin_wrapper_path.display());
 
     fs::write(&desktop_path, desktop_content).into_diagnostic()?;
+    user.chown(&desktop_path)?;
+
+    record_wrapper(WrapperEntry {
+        name: wrapper_name.to_string(),
+        app_type: AppType::Gui,
+        inner_cmd: inner_cmd.to_string(),
+        desktop_file: Some(desktop_path.clone()),
+        gpu: passthrough.gpu,
+        audio: passthrough.audio,
+        container: container().to_string(),
+    })?;
 
     Logger::success(&format!("GUI installed. Wrapper: {}, Desktop: {}", bin_wrapper_path.display(), desktop_path.display()));
     Ok(())
 }
 
-fn handle_remove(package: String) -> Result<()> {
+fn handle_remove(package: String, distro: Option<ContainerProfile>) -> Result<()> {
+    let profile = ContainerProfile::resolve(distro)?;
+    let entry = load_manifest().and_then(|entries| entries.into_iter().find(|e| e.name == package));
+    // The profile and the uninstall below belong to this run's box, not the app's
+    if let Some(entry) = entry.as_ref().filter(|e| e.container != container()) {
+        return Err(HammerError::ConfigError(format!(
+            "{} is installed in {}, not {}. Pass the --box it was installed with.",
+            package, entry.container, container()
+        )).into());
+    }
+
     // Remove wrapper
-    let wrapper_path = Path::new(WRAPPER_DIR).join(&package);
+    let home = InvokingUser::get()?.home;
+    let wrapper_path = home.join(WRAPPER_DIR).join(&package);
     if wrapper_path.exists() {
         fs::remove_file(wrapper_path).into_diagnostic()?;
         Logger::success(&format!("Removed binary wrapper for {}", package));
     }
 
-    let desktop_path = Path::new(DESKTOP_DIR).join(format!("{}.desktop", package));
+    let desktop_path = entry
+    .and_then(|e| e.desktop_file)
+    .unwrap_or_else(|| home.join(DESKTOP_DIR).join(format!("{}.desktop", package)));
     if desktop_path.exists() {
         fs::remove_file(desktop_path).into_diagnostic()?;
         Logger::success("Removed .desktop file");
     }
 
+    if load_manifest().is_some() {
+        update_manifest(|entries| entries.retain(|e| e.name != package))?;
+    }
+
     // Optional: Remove from container
     if Confirm::new().with_prompt("Uninstall from container as well?").interact().into_diagnostic()? {
-        run_command("podman", &["exec", CONTAINER_NAME, "apt-get", "remove", "-y", &package], "Apt Remove")?;
+        remove_from_container(profile, &package)?;
     }
 
     Ok(())
 }
 
-fn handle_list() -> Result<()> {
+fn handle_search(query: &str, limit: usize) -> Result<()> {
+    let profile = ContainerProfile::resolve(None)?;
+    ensure_container_exists(profile, Passthrough::recorded())?;
+
+    let mut search = vec!["exec", container()];
+    search.extend(profile.search_cmd());
+    search.push(query);
+    let output = podman(&search, "Search Packages")?;
+
+    let lines: Vec<&str> = output.lines().filter(|l| !l.trim().is_empty()).collect();
+    if lines.is_empty() {
+        Logger::info(&format!("No packages match '{}'.", query));
+        return Ok(());
+    }
+
+    for line in lines.iter().take(limit) {
+        match line.split_once(' ') {
+            Some((name, rest)) if !line.starts_with(' ') => println!(" {} {}", name.if_supports_color(Stream::Stdout, |t| t.cyan()), rest),
+            _ => println!(" {}", line.if_supports_color(Stream::Stdout, |t| t.bright_black())),
+        }
+    }
+    if lines.len() > limit {
+        Logger::info(&format!("{} more lines, use --limit to see them.", lines.len() - limit));
+    }
+    Ok(())
+}
+
+fn handle_list(all: bool) -> Result<()> {
+    if all {
+        Logger::info("Hammer boxes:");
+        let output = podman(&["ps", "-a", "--format", "{{.Names}}\t{{.State}}"], "List Containers")?;
+        for line in output.lines() {
+            let (name, state) = line.split_once('\t').unwrap_or((line, ""));
+            if name == CONTAINER_NAME || name.starts_with(&format!("{}-", CONTAINER_NAME)) {
+                println!(
+                    " - {} {}",
+                    name.if_supports_color(Stream::Stdout, |t| t.cyan()),
+                    state.if_supports_color(Stream::Stdout, |t| t.bright_black())
+                );
+            }
+        }
+    }
+
     Logger::info("Installed container wrappers:");
-    for entry in fs::read_dir(WRAPPER_DIR).into_diagnostic()? {
+    if let Some(entries) = load_manifest() {
+        for entry in entries.into_iter().filter(|e| all || e.container == container()) {
+            let kind = match entry.app_type {
+                AppType::Cli => "cli",
+                AppType::Gui => "gui",
+            };
+            if all {
+                println!(
+                    " - {} {} {} {}",
+                    entry.name.if_supports_color(Stream::Stdout, |t| t.cyan()),
+                    kind.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                    entry.inner_cmd.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                    entry.container.if_supports_color(Stream::Stdout, |t| t.bright_black())
+                );
+            } else {
+                println!(
+                    " - {} {} {}",
+                    entry.name.if_supports_color(Stream::Stdout, |t| t.cyan()),
+                    kind.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                    entry.inner_cmd.if_supports_color(Stream::Stdout, |t| t.bright_black())
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    // No manifest yet, fall back to finding wrappers by their contents
+    let wrapper_dir = InvokingUser::get()?.home.join(WRAPPER_DIR);
+    if !wrapper_dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(wrapper_dir).into_diagnostic()? {
         let entry = entry.into_diagnostic()?;
         let path = entry.path();
         if path.is_file() {
             let content = fs::read_to_string(&path).unwrap_or_default();
             if content.contains("podman exec") {
-                println!(" - {}", path.file_name().unwrap().to_string_lossy().cyan());
+                println!(" - {}", path.file_name().unwrap().to_string_lossy().if_supports_color(Stream::Stdout, |t| t.cyan()));
             }
         }
     }
     Ok(())
 }
+
+/// Extracts the command a wrapper script runs inside the container.
+fn wrapper_inner_command(wrapper_name: &str) -> Option<String> {
+    if let Some(entry) = load_manifest().and_then(|entries| entries.into_iter().find(|e| e.name == wrapper_name)) {
+        return Some(entry.inner_cmd);
+    }
+    let wrapper_dir = InvokingUser::get().ok()?.home.join(WRAPPER_DIR);
+    let content = fs::read_to_string(wrapper_dir.join(wrapper_name)).ok()?;
+    let exec_line = content.lines().find(|l| l.contains("podman exec"))?;
+    let mut tokens = exec_line.split_whitespace().skip_while(|t| !t.starts_with(CONTAINER_NAME));
+    tokens.next()?;
+    tokens.next().map(|t| t.to_string())
+}
+
+fn handle_logs(package: String, debug_run: bool, follow: bool) -> Result<()> {
+    ensure_container_exists(ContainerProfile::resolve(None)?, Passthrough::recorded())?;
+
+    // The box's own log only has its `sleep infinity`, so every mode runs the app itself
+    let inner_cmd = wrapper_inner_command(&package).unwrap_or_else(|| package.clone());
+
+    if follow {
+        Logger::info(&format!("Running {} in {}, streaming its output...", inner_cmd.if_supports_color(Stream::Stdout, |t| t.cyan()), container()));
+        let status = podman_command()
+        .args(&["exec", "-e", "DISPLAY", "-e", "WAYLAND_DISPLAY", "-e", "XDG_RUNTIME_DIR", container(), &inner_cmd])
+        .status()
+        .into_diagnostic()?;
+        Logger::info(&format!("{} exited with {}", inner_cmd, status));
+        return Ok(());
+    }
+
+    if debug_run {
+        Logger::info(&format!("Running {} attached with debug output...", inner_cmd.if_supports_color(Stream::Stdout, |t| t.cyan())));
+        let status = podman_command()
+        .args(&[
+            "exec", "-it",
+            "-e", "DISPLAY", "-e", "WAYLAND_DISPLAY", "-e", "XDG_RUNTIME_DIR",
+            "-e", "G_MESSAGES_DEBUG=all",
+            "-e", "QT_LOGGING_RULES=*.debug=true",
+            "-e", "LIBGL_DEBUG=verbose",
+            container(), &inner_cmd,
+        ])
+        .status()
+        .into_diagnostic()?;
+        Logger::info(&format!("{} exited with {}", inner_cmd, status));
+        return Ok(());
+    }
+
+    Logger::info(&format!("Running {} in {} (output captured)...", inner_cmd.if_supports_color(Stream::Stdout, |t| t.cyan()), container()));
+    let output = podman_command()
+    .args(&["exec", "-e", "DISPLAY", "-e", "WAYLAND_DISPLAY", "-e", "XDG_RUNTIME_DIR", container(), &inner_cmd])
+    .output()
+    .into_diagnostic()?;
+
+    println!("{}", "── stdout ──".if_supports_color(Stream::Stdout, |t| t.bright_black()));
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    println!("{}", "── stderr ──".if_supports_color(Stream::Stdout, |t| t.bright_black()));
+    print!("{}", String::from_utf8_lossy(&output.stderr).if_supports_color(Stream::Stdout, |t| t.red()));
+
+    if output.status.success() {
+        Logger::success(&format!("{} exited cleanly.", inner_cmd));
+    } else {
+        Logger::error(&format!("{} exited with {}", inner_cmd, output.status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_state_matches_whole_names() {
+        let output = "hammer-box-old\texited\nhammer-box-dev\tRunning\nhammer-box\tcreated\n";
+        assert_eq!(find_state(output, "hammer-box").as_deref(), Some("created"));
+        assert_eq!(find_state(output, "hammer-box-dev").as_deref(), Some("running"));
+        assert_eq!(find_state(output, "hammer"), None);
+        assert_eq!(find_state("", "hammer-box"), None);
+    }
+}
@@ -1,15 +1,85 @@
 use miette::{Diagnostic, IntoDiagnostic, Result, WrapErr};
 use indicatif::{ProgressBar, ProgressStyle};
-use owo_colors::OwoColorize;
+use owo_colors::{OwoColorize, Stream};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{self, OpenOptions};
-use std::io::{Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use walkdir::WalkDir;
 
 pub const LOG_DIR: &str = "/var/log/hammer";
 pub const MOUNT_POINT: &str = "/run/hammer/btrfs-root";
+pub const ROOT_SUBVOL: &str = "@";
+pub const SNAPSHOTS_SUBVOL: &str = "@snapshots";
+pub const LOCK_FILE: &str = "/run/hammer.lock";
+pub const APT_SOURCES_LIST: &str = "/etc/apt/sources.list";
+pub const APT_SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+/// `clean --logs` truncates the active log once it grows past this size.
+pub const LOG_TRUNCATE_SIZE: u64 = 10 * 1024 * 1024;
+/// hammer.log is rotated to hammer.log.1 once it grows past this size
+pub const LOG_ROTATE_SIZE: u64 = 5 * 1024 * 1024;
+/// Rotated logs kept, hammer.log.1 (newest) to hammer.log.N
+pub const LOG_ROTATE_KEEP: u32 = 5;
+/// Attempts and first back-off delay for commands that go over the network
+pub const NETWORK_RETRIES: u32 = 3;
+pub const NETWORK_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How long a timed-out command gets to exit on SIGTERM before it is killed
+pub const KILL_GRACE: Duration = Duration::from_secs(30);
+
+/// Filesystem locations hammer works with. Each defaults to the constant of the
+/// same name and can be overridden with `HAMMER_LOG_DIR`, `HAMMER_MOUNT_POINT`,
+/// `HAMMER_ROOT_SUBVOL`, `HAMMER_SNAPSHOTS_SUBVOL` or `HAMMER_LOCK_FILE`.
+pub struct Paths {
+    pub log_dir: PathBuf,
+    pub mount_point: PathBuf,
+    pub root_subvol: String,
+    pub snapshots_subvol: String,
+    pub lock_file: PathBuf,
+}
+
+lazy_static::lazy_static! {
+    static ref PATHS: Paths = Paths::from_env();
+    /// One line of `btrfs subvolume list`. Extra columns (`cgen`, `parent`,
+    /// `uuid`, ...) come before `path`, which runs to the end of the line.
+    static ref SUBVOL_LINE: regex::Regex = regex::Regex::new(r"^ID \d+ .*?\bpath (.+)$").unwrap();
+}
+
+impl Paths {
+    pub fn get() -> &'static Paths {
+        &PATHS
+    }
+
+    fn from_env() -> Self {
+        let var = |name: &str, default: &str| {
+            std::env::var(name).ok().filter(|v| !v.is_empty()).unwrap_or_else(|| default.to_string())
+        };
+        Paths {
+            log_dir: var("HAMMER_LOG_DIR", LOG_DIR).into(),
+            mount_point: var("HAMMER_MOUNT_POINT", MOUNT_POINT).into(),
+            root_subvol: var("HAMMER_ROOT_SUBVOL", ROOT_SUBVOL),
+            snapshots_subvol: var("HAMMER_SNAPSHOTS_SUBVOL", SNAPSHOTS_SUBVOL),
+            lock_file: var("HAMMER_LOCK_FILE", LOCK_FILE).into(),
+        }
+    }
+
+    /// The root subvolume as seen under the top-level mount.
+    pub fn root_subvol_path(&self) -> PathBuf {
+        self.mount_point.join(&self.root_subvol)
+    }
+
+    /// The snapshots subvolume as seen under the top-level mount.
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.mount_point.join(&self.snapshots_subvol)
+    }
+}
 
 #[derive(Error, Debug, Diagnostic)]
 pub enum HammerError {
@@ -28,58 +98,348 @@ pub enum HammerError {
     #[error("Btrfs Error: {0}")]
     #[diagnostic(code(hammer::btrfs_error), help("Ensure / is a Btrfs subvolume and layout uses @."))]
     BtrfsError(String),
+
+    #[error("Lock Error: {0}")]
+    #[diagnostic(code(hammer::lock_error), help("Another hammer operation is running. Use --force to override."))]
+    LockError(String),
+
+    #[error("Not enough disk space: {0}")]
+    #[diagnostic(code(hammer::space_error), help("Free space with 'hammer clean' or pass --skip-space-check."))]
+    SpaceError(String),
+}
+
+/// Metadata stored next to each snapshot as `@snapshots/<name>.meta.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// What the snapshot was taken for, e.g. `pre-update` or `pre-layer`
+    pub kind: String,
+    /// Kernel the system was running when the snapshot was taken
+    pub kernel: String,
+    /// RFC3339 creation time
+    #[serde(default)]
+    pub created_at: String,
+    /// Apparent size of the snapshot tree in bytes
+    #[serde(default)]
+    pub size_bytes: u64,
+    /// SHA-256 over the snapshot's sorted `name=version` package list
+    #[serde(default)]
+    pub system_version: String,
+    /// Release codename of the snapshotted system, from /etc/os-release
+    #[serde(default)]
+    pub suite: String,
+    /// Set by `set-suite` on its restore point to the suite being upgraded to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_suite: Option<String>,
+    /// Active APT source entries the snapshotted system pulled packages from
+    #[serde(default)]
+    pub apt_sources: Vec<String>,
+    /// Set by `pin`; clean and prune never delete a pinned snapshot
+    #[serde(default)]
+    pub pinned: bool,
+    /// Free-form note given to a manual snapshot with `--description`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 pub struct Logger;
 
+/// Line format of hammer.log. The console output is the same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[timestamp] LEVEL: message`
+    Text,
+    /// One `{"timestamp", "level", "message"}` object per line
+    Json,
+}
+
+// 0 = not chosen yet, read HAMMER_LOG_FORMAT on first use
+static LOG_FORMAT: AtomicU8 = AtomicU8::new(0);
+
+/// How much Logger prints to the console. hammer.log always gets everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+// 0 = not chosen yet, read HAMMER_LOG_LEVEL on first use, otherwise level + 1
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(0);
+
 impl Logger {
     pub fn init() -> Result<()> {
-        if !Path::new(LOG_DIR).exists() {
-            fs::create_dir_all(LOG_DIR).into_diagnostic()?;
+        let log_dir = &Paths::get().log_dir;
+        if !log_dir.exists() {
+            fs::create_dir_all(log_dir).into_diagnostic()?;
         }
         Ok(())
     }
 
+    /// Overrides `HAMMER_LOG_FORMAT` for the rest of the process.
+    pub fn set_format(format: LogFormat) {
+        LOG_FORMAT.store(if format == LogFormat::Json { 2 } else { 1 }, Ordering::Relaxed);
+    }
+
+    /// Overrides `HAMMER_LOG_LEVEL` for the rest of the process.
+    pub fn set_level(level: LogLevel) {
+        LOG_LEVEL.store(level as u8 + 1, Ordering::Relaxed);
+    }
+
+    /// Forces console colours on or off for the rest of the process. Without
+    /// this, colours are used only on a terminal and when `NO_COLOR` is unset.
+    pub fn set_color(enabled: bool) {
+        owo_colors::set_override(enabled);
+    }
+
+    pub fn level() -> LogLevel {
+        match LOG_LEVEL.load(Ordering::Relaxed) {
+            1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            3 => LogLevel::Info,
+            4 => LogLevel::Debug,
+            _ => {
+                let level = match std::env::var("HAMMER_LOG_LEVEL").unwrap_or_default().to_lowercase().as_str() {
+                    "error" => LogLevel::Error,
+                    "warn" => LogLevel::Warn,
+                    "debug" => LogLevel::Debug,
+                    _ => LogLevel::Info,
+                };
+                Self::set_level(level);
+                level
+            }
+        }
+    }
+
+    fn enabled(level: LogLevel) -> bool {
+        level <= Self::level()
+    }
+
+    fn format() -> LogFormat {
+        match LOG_FORMAT.load(Ordering::Relaxed) {
+            1 => LogFormat::Text,
+            2 => LogFormat::Json,
+            _ => {
+                let json = std::env::var("HAMMER_LOG_FORMAT").is_ok_and(|v| v.eq_ignore_ascii_case("json"));
+                let format = if json { LogFormat::Json } else { LogFormat::Text };
+                Self::set_format(format);
+                format
+            }
+        }
+    }
+
+    /// Writes a line without a level to hammer.log only.
     pub fn log(message: &str) {
-        let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
-        let log_line = format!("[{}] {}\n", timestamp, message);
+        Self::record(None, message);
+    }
+
+    fn record(level: Option<&str>, message: &str) {
+        let now = chrono::Local::now();
+        let log_line = match (Self::format(), level) {
+            (LogFormat::Json, level) => format!(
+                "{}\n",
+                serde_json::json!({
+                    "timestamp": now.to_rfc3339(),
+                    "level": level.unwrap_or("DEBUG").to_lowercase(),
+                    "message": message,
+                })
+            ),
+            (LogFormat::Text, Some(level)) => format!("[{}] {}: {}\n", now.format("%Y-%m-%d %H:%M:%S"), level, message),
+            (LogFormat::Text, None) => format!("[{}] {}\n", now.format("%Y-%m-%d %H:%M:%S"), message),
+        };
 
-        let log_file = Path::new(LOG_DIR).join("hammer.log");
+        let log_file = Paths::get().log_dir.join("hammer.log");
+        if fs::metadata(&log_file).map(|m| m.len() > LOG_ROTATE_SIZE).unwrap_or(false) {
+            Self::rotate(&Paths::get().log_dir);
+        }
         if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log_file) {
             let _ = file.write_all(log_line.as_bytes());
         }
     }
 
+    /// Shifts hammer.log.N to hammer.log.N+1, dropping the oldest beyond
+    /// `LOG_ROTATE_KEEP`, and moves hammer.log to hammer.log.1.
+    fn rotate(log_dir: &Path) {
+        let rotated = |n: u32| log_dir.join(format!("hammer.log.{}", n));
+        let _ = fs::remove_file(rotated(LOG_ROTATE_KEEP));
+        for n in (1..LOG_ROTATE_KEEP).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        let _ = fs::rename(log_dir.join("hammer.log"), rotated(1));
+    }
+
+    /// Deletes rotated `hammer.log.*` files older than `keep_days` and truncates
+    /// the active log if it exceeds `LOG_TRUNCATE_SIZE`. Returns the bytes freed.
+    pub fn clean(keep_days: u64) -> Result<u64> {
+        let log_dir = &Paths::get().log_dir;
+        if !log_dir.exists() {
+            return Ok(0);
+        }
+
+        let max_age = Duration::from_secs(keep_days * 24 * 60 * 60);
+        let mut freed = 0;
+
+        for entry in fs::read_dir(log_dir).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("hammer.log.") {
+                continue;
+            }
+
+            let metadata = entry.metadata().into_diagnostic()?;
+            let age = metadata.modified().into_diagnostic()?.elapsed().unwrap_or_default();
+            if metadata.is_file() && age > max_age {
+                fs::remove_file(entry.path()).into_diagnostic()?;
+                freed += metadata.len();
+            }
+        }
+
+        let active = log_dir.join("hammer.log");
+        if let Ok(metadata) = fs::metadata(&active) {
+            if metadata.len() > LOG_TRUNCATE_SIZE {
+                OpenOptions::new().write(true).truncate(true).open(&active).into_diagnostic()?;
+                freed += metadata.len();
+            }
+        }
+
+        Ok(freed)
+    }
+
+    pub fn debug(message: &str) {
+        if Self::enabled(LogLevel::Debug) {
+            println!(
+                " {} {}",
+                "·".if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                message.if_supports_color(Stream::Stdout, |t| t.bright_black())
+            );
+        }
+        Self::record(Some("DEBUG"), message);
+    }
+
     pub fn info(message: &str) {
-        println!(" {} {}", "│".blue(), message);
-        Self::log(&format!("INFO: {}", message));
+        if Self::enabled(LogLevel::Info) {
+            println!(" {} {}", "│".if_supports_color(Stream::Stdout, |t| t.blue()), message);
+        }
+        Self::record(Some("INFO"), message);
     }
 
     pub fn section(title: &str) {
-        println!("\n{} {}", "┌──".magenta(), title.magenta().bold());
+        if Self::enabled(LogLevel::Info) {
+            println!(
+                "\n{} {}",
+                "┌──".if_supports_color(Stream::Stdout, |t| t.magenta()),
+                title.if_supports_color(Stream::Stdout, |t| t.magenta().bold())
+            );
+        }
     }
 
     pub fn end_section() {
-        println!("{}", "└──".magenta());
+        if Self::enabled(LogLevel::Info) {
+            println!("{}", "└──".if_supports_color(Stream::Stdout, |t| t.magenta()));
+        }
     }
 
     pub fn error(message: &str) {
-        eprintln!(" {} {}", "✖".red(), message.red());
-        Self::log(&format!("ERROR: {}", message));
+        eprintln!(
+            " {} {}",
+            "✖".if_supports_color(Stream::Stderr, |t| t.red()),
+            message.if_supports_color(Stream::Stderr, |t| t.red())
+        );
+        Self::record(Some("ERROR"), message);
     }
 
     pub fn success(message: &str) {
-        println!(" {} {}", "✓".green(), message.green());
-        Self::log(&format!("SUCCESS: {}", message));
+        if Self::enabled(LogLevel::Info) {
+            println!(
+                " {} {}",
+                "✓".if_supports_color(Stream::Stdout, |t| t.green()),
+                message.if_supports_color(Stream::Stdout, |t| t.green())
+            );
+        }
+        Self::record(Some("SUCCESS"), message);
     }
 
     pub fn warn(message: &str) {
-        println!(" {} {}", "!".yellow(), message.yellow());
-        Self::log(&format!("WARN: {}", message));
+        if Self::enabled(LogLevel::Warn) {
+            println!(
+                " {} {}",
+                "!".if_supports_color(Stream::Stdout, |t| t.yellow()),
+                message.if_supports_color(Stream::Stdout, |t| t.yellow())
+            );
+        }
+        Self::record(Some("WARN"), message);
+    }
+}
+
+/// Best-effort apparent size of the files under `path`. Entries that cannot be
+/// read are skipped, and symlinks and other filesystems are not followed.
+pub fn calculate_dir_size(path: &Path) -> Result<u64> {
+    calculate_dir_size_filtered(path, |_| false)
+}
+
+/// `calculate_dir_size` that also skips every path for which `exclude` returns
+/// true, along with everything below it (e.g. `/proc` or `/sys`).
+pub fn calculate_dir_size_filtered<F: Fn(&Path) -> bool>(path: &Path, exclude: F) -> Result<u64> {
+    let mut total = 0;
+    let walker = WalkDir::new(path)
+    .follow_links(false)
+    .same_file_system(true)
+    .into_iter()
+    .filter_entry(|e| !exclude(e.path()));
+
+    for entry in walker {
+        let metadata = match entry.and_then(|e| e.metadata()) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                Logger::debug(&format!("Skipping while sizing {}: {}", path.display(), err));
+                continue;
+            }
+        };
+        if metadata.is_file() {
+            total += metadata.len();
+        }
+    }
+    Ok(total)
+}
+
+/// Errors out unless the filesystem holding `path` has at least `required` bytes free.
+pub fn check_free_space(path: &Path, required: u64) -> Result<()> {
+    const MB: u64 = 1024 * 1024;
+    let stat = nix::sys::statvfs::statvfs(path).into_diagnostic()?;
+    let available = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    if available < required {
+        return Err(HammerError::SpaceError(format!(
+            "{} MB available on {}, {} MB required", available / MB, path.display(), required / MB
+        )).into());
+    }
+    Ok(())
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// indicatif draws nothing when stderr isn't a terminal (journald, pipes), so
+/// progress helpers print their message once as a plain line there instead.
+fn announce_without_tty(msg: &str) {
+    if !std::io::stderr().is_terminal() {
+        eprintln!("{}", msg);
     }
 }
 
 pub fn create_progress_bar(len: u64, msg: &str) -> ProgressBar {
+    announce_without_tty(msg);
     let pb = ProgressBar::new(len);
     pb.set_style(
         ProgressStyle::default_bar()
@@ -92,6 +452,7 @@ pub fn create_progress_bar(len: u64, msg: &str) -> ProgressBar {
 }
 
 pub fn create_spinner(msg: &str) -> ProgressBar {
+    announce_without_tty(msg);
     let pb = ProgressBar::new_spinner();
     pb.set_style(
         ProgressStyle::default_spinner()
@@ -104,8 +465,26 @@ pub fn create_spinner(msg: &str) -> ProgressBar {
     pb
 }
 
-pub fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<String> {
-    Logger::log(&format!("Running: {} {}", cmd, args.join(" ")));
+/// Exit code and captured output of a command run by `run_command_status`.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    /// `None` when the command was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Runs a command and hands back its exit code and output whatever it was.
+/// Only failing to start the command is an error; use this where the exit
+/// code means something, e.g. apt's 100 or `mountpoint`'s 32.
+pub fn run_command_status(cmd: &str, args: &[&str]) -> Result<CommandOutput> {
+    Logger::debug(&format!("Running: {} {}", cmd, args.join(" ")));
 
     let output = Command::new(cmd)
     .args(args)
@@ -115,21 +494,130 @@ pub fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<String
     .into_diagnostic()
     .wrap_err(format!("Failed to execute binary: {}", cmd))?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        Logger::log(&format!("Command failed stderr: {}", stderr));
-        return Err(HammerError::CommandFailed(format!("{} failed: {}", description, stderr)).into());
+    Ok(CommandOutput {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+pub fn run_command(cmd: &str, args: &[&str], description: &str) -> Result<String> {
+    let output = run_command_status(cmd, args)?;
+
+    if !output.success() {
+        Logger::log(&format!("Command failed stderr: {}", output.stderr));
+        return Err(HammerError::CommandFailed(format!("{} failed: {}", description, output.stderr)).into());
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(output.stdout)
+}
+
+/// `run_command` for network-bound commands. Retries up to `attempts` times,
+/// doubling `base_delay` after each failure, and returns the last error.
+pub fn run_command_retry(cmd: &str, args: &[&str], description: &str, attempts: u32, base_delay: Duration) -> Result<String> {
+    let mut delay = base_delay;
+    let mut attempt = 1;
+    loop {
+        match run_command(cmd, args, description) {
+            Ok(output) => return Ok(output),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(_) => {
+                Logger::warn(&format!(
+                    "{} failed (attempt {}/{}), retrying in {}s...",
+                    description, attempt, attempts, delay.as_secs()
+                ));
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// `run_command` with a deadline. On expiry the whole process group is killed
+/// and the error carries whatever the command printed up to that point.
+pub fn run_command_timeout(cmd: &str, args: &[&str], description: &str, timeout: Duration) -> Result<String> {
+    Logger::debug(&format!("Running (timeout {}s): {} {}", timeout.as_secs(), cmd, args.join(" ")));
+
+    let mut child = Command::new(cmd)
+    .args(args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .process_group(0)
+    .spawn()
+    .into_diagnostic()
+    .wrap_err(format!("Failed to execute binary: {}", cmd))?;
+
+    // Drain both pipes while waiting, a full pipe would stall the child
+    let stdout = child.stdout.take().map(drain_pipe);
+    let stderr = child.stderr.take().map(drain_pipe);
+    let status = wait_or_kill(&mut child, timeout)?;
+    let stdout = stdout.and_then(|h| h.join().ok()).unwrap_or_default();
+    let stderr = stderr.and_then(|h| h.join().ok()).unwrap_or_default();
+
+    match status {
+        None => {
+            Logger::log(&format!("Command timed out, stderr: {}", stderr));
+            Err(HammerError::CommandFailed(format!(
+                "{} timed out after {}s\nstdout:\n{}\nstderr:\n{}",
+                description, timeout.as_secs(), stdout, stderr
+            )).into())
+        }
+        Some(status) if !status.success() => {
+            Logger::log(&format!("Command failed stderr: {}", stderr));
+            Err(HammerError::CommandFailed(format!("{} failed: {}", description, stderr)).into())
+        }
+        Some(_) => Ok(stdout),
+    }
+}
+
+/// Waits for `child` for at most `timeout`. On expiry its process group gets
+/// SIGTERM, so dpkg and the like can finish the package at hand, and SIGKILL
+/// if it is still there after `KILL_GRACE`; `None` is returned either way.
+/// Spawn it with `process_group(0)` to take its own children down with it.
+pub fn wait_or_kill(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let pgid = nix::unistd::Pid::from_raw(child.id() as i32);
+    if let Some(status) = wait_until(child, Instant::now() + timeout)? {
+        return Ok(Some(status));
+    }
+
+    let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGTERM);
+    if wait_until(child, Instant::now() + KILL_GRACE)?.is_none() {
+        let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL);
+        let _ = child.wait();
+    }
+    Ok(None)
+}
+
+/// Polls `child` until it exits or `deadline` passes.
+fn wait_until(child: &mut Child, deadline: Instant) -> Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait().into_diagnostic()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn drain_pipe<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).to_string()
+    })
 }
 
 // --- Btrfs Helpers ---
 
 /// Mounts the top-level Btrfs root (ID 5) to a temporary location
 pub fn mount_btrfs_root() -> Result<String> {
-    if !Path::new(MOUNT_POINT).exists() {
-        fs::create_dir_all(MOUNT_POINT).into_diagnostic()?;
+    let mount_point = Paths::get().mount_point.to_string_lossy().to_string();
+    if !Path::new(&mount_point).exists() {
+        fs::create_dir_all(&mount_point).into_diagnostic()?;
     }
 
     // Identify the device / is mounted on
@@ -144,63 +632,363 @@ pub fn mount_btrfs_root() -> Result<String> {
 
     // Mount subvolid=5
     let status = Command::new("mount")
-    .args(&["-t", "btrfs", "-o", "subvolid=5", device, MOUNT_POINT])
+    .args(&["-t", "btrfs", "-o", "subvolid=5", device, &mount_point])
     .output()
     .into_diagnostic()?;
 
     if !status.status.success() {
         // Check if already mounted
         let check = run_command("mount", &[], "Check mounts")?;
-        if check.contains(MOUNT_POINT) {
-            return Ok(MOUNT_POINT.to_string());
+        if check.contains(&mount_point) {
+            return Ok(mount_point);
         }
         return Err(HammerError::BtrfsError("Failed to mount Btrfs top-level root".into()).into());
     }
 
-    Ok(MOUNT_POINT.to_string())
+    Ok(mount_point)
 }
 
 pub fn umount_btrfs_root() -> Result<()> {
     // Attempt unmount, but don't fail hard if it fails (it might be lazy unmounted later by OS)
-    let _ = run_command("umount", &[MOUNT_POINT], "Unmount Btrfs Root");
+    let mount_point = Paths::get().mount_point.to_string_lossy().to_string();
+    let _ = run_command("umount", &[&mount_point], "Unmount Btrfs Root");
+    Ok(())
+}
+
+/// Subvolumes nested anywhere below `subvol`, relative to it, parents before
+/// children. `subvol` must lie under the mounted top level.
+pub fn btrfs_nested_subvolumes(subvol: &Path) -> Result<Vec<PathBuf>> {
+    let top = &Paths::get().mount_point;
+    let rel = subvol.strip_prefix(top).into_diagnostic()?;
+    let output = run_command("btrfs", &["subvolume", "list", &top.to_string_lossy()], "List Subvolumes")?;
+
+    let mut nested: Vec<PathBuf> = parse_subvolume_list(&output)
+    .into_iter()
+    .filter_map(|path| path.strip_prefix(rel).ok().map(Path::to_path_buf))
+    .filter(|path| !path.as_os_str().is_empty())
+    .collect();
+    nested.sort_by_key(|path| path.components().count());
+    Ok(nested)
+}
+
+/// Subvolume paths from `btrfs subvolume list` output, relative to the top
+/// level. `-a` output's `<FS_TREE>/` prefix is dropped; lines that don't look
+/// like subvolume entries are skipped rather than trusted.
+pub fn parse_subvolume_list(output: &str) -> Vec<PathBuf> {
+    output
+    .lines()
+    .filter_map(|line| match SUBVOL_LINE.captures(line) {
+        Some(caps) => Some(caps[1].to_string()),
+        None => {
+            if !line.trim().is_empty() {
+                Logger::debug(&format!("Ignoring unexpected subvolume list line: {}", line));
+            }
+            None
+        }
+    })
+    .map(|path| PathBuf::from(path.strip_prefix("<FS_TREE>/").unwrap_or(&path)))
+    .collect()
+}
+
+/// Snapshots `src` to `dest` including its nested subvolumes, which a plain
+/// `btrfs subvolume snapshot` leaves behind as empty directories.
+pub fn btrfs_snapshot_tree(src: &Path, dest: &Path) -> Result<()> {
+    run_command("btrfs", &["subvolume", "snapshot", &src.to_string_lossy(), &dest.to_string_lossy()], "Create Snapshot")?;
+
+    for rel in btrfs_nested_subvolumes(src)? {
+        let placeholder = dest.join(&rel);
+        if placeholder.is_dir() {
+            fs::remove_dir(&placeholder).into_diagnostic()?;
+        }
+        run_command(
+            "btrfs",
+            &["subvolume", "snapshot", &src.join(&rel).to_string_lossy(), &placeholder.to_string_lossy()],
+            "Snapshot Nested Subvolume",
+        )?;
+    }
     Ok(())
 }
 
-pub fn btrfs_snapshot_atomic(name: &str) -> Result<()> {
+/// Deletes `path` and the subvolumes nested in it, children first.
+pub fn btrfs_delete_tree(path: &Path) -> Result<()> {
+    for rel in btrfs_nested_subvolumes(path)?.iter().rev() {
+        run_command("btrfs", &["subvolume", "delete", &path.join(rel).to_string_lossy()], "Delete Nested Subvolume")?;
+    }
+    run_command("btrfs", &["subvolume", "delete", &path.to_string_lossy()], "Delete Snapshot")?;
+    Ok(())
+}
+
+pub fn btrfs_snapshot_atomic(name: &str, kind: &str) -> Result<()> {
     // Requires @ layout
     mount_btrfs_root()?;
 
-    let root_subvol = Path::new(MOUNT_POINT).join("@");
-    let snap_dir = Path::new(MOUNT_POINT).join("@snapshots");
+    let root_subvol = Paths::get().root_subvol_path();
+    let snap_dir = Paths::get().snapshots_dir();
     let snap_target = snap_dir.join(name);
 
     if !root_subvol.exists() {
         umount_btrfs_root()?;
-        return Err(HammerError::BtrfsError("Subvolume @ not found. Hammer requires @ layout.".into()).into());
+        return Err(HammerError::BtrfsError(format!(
+            "Subvolume {} not found. Hammer requires @ layout.", Paths::get().root_subvol
+        )).into());
     }
 
     if !snap_dir.exists() {
         fs::create_dir_all(&snap_dir).into_diagnostic()?;
     }
 
-    let src = root_subvol.to_string_lossy();
-    let dest = snap_target.to_string_lossy();
+    btrfs_snapshot_tree(&root_subvol, &snap_target)?;
+
+    // A separate /boot is not part of @, give the snapshot its own copy
+    if boot_is_separate() {
+        copy_boot_images(Path::new("/boot"), &snap_target.join("boot"))?;
+    }
 
-    run_command("btrfs", &["subvolume", "snapshot", &src, &dest], "Create Snapshot")?;
+    let kernel = run_command("uname", &["-r"], "Detect Kernel")?;
+    let meta = SnapshotMeta {
+        kind: kind.to_string(),
+        kernel: kernel.trim().to_string(),
+        created_at: chrono::Local::now().to_rfc3339(),
+        size_bytes: calculate_dir_size(&snap_target)?,
+        system_version: compute_system_version(&snap_target)?,
+        suite: system_suite(&snap_target).unwrap_or_default(),
+        target_suite: None,
+        apt_sources: apt_source_entries(&snap_target).into_iter().collect(),
+        pinned: false,
+        description: None,
+    };
+    write_snapshot_meta(&snap_dir, name, &meta)?;
 
     umount_btrfs_root()?;
     Ok(())
 }
 
+fn snap_meta_path(snap_dir: &Path, name: &str) -> PathBuf {
+    snap_dir.join(format!("{}.meta.json", name))
+}
+
+fn write_snapshot_meta(snap_dir: &Path, name: &str, meta: &SnapshotMeta) -> Result<()> {
+    let meta_json = serde_json::to_string_pretty(meta).into_diagnostic()?;
+    fs::write(snap_meta_path(snap_dir, name), meta_json).into_diagnostic()
+}
+
+/// Applies `update` to a snapshot's metadata and writes it back. Snapshots
+/// without metadata start from `SnapshotMeta::default()`.
+pub fn btrfs_update_snapshot_meta<F: FnOnce(&mut SnapshotMeta)>(name: &str, update: F) -> Result<()> {
+    mount_btrfs_root()?;
+    let snap_dir = Paths::get().snapshots_dir();
+
+    if !snap_dir.join(name).exists() {
+        umount_btrfs_root()?;
+        return Err(HammerError::BtrfsError(format!("Snapshot {} not found", name)).into());
+    }
+
+    let mut meta: SnapshotMeta = fs::read_to_string(snap_meta_path(&snap_dir, name))
+    .ok()
+    .and_then(|content| serde_json::from_str(&content).ok())
+    .unwrap_or_default();
+    update(&mut meta);
+    let result = write_snapshot_meta(&snap_dir, name, &meta);
+
+    umount_btrfs_root()?;
+    result
+}
+
+/// Returns the `name -> version` map of packages installed in the system tree at `root`.
+/// Names carry their architecture where dpkg needs it, e.g. `libc6:i386`.
+pub fn installed_packages(root: &Path) -> Result<BTreeMap<String, String>> {
+    let admindir = format!("--admindir={}", root.join("var/lib/dpkg").display());
+    let output = run_command(
+        "dpkg-query",
+        &[&admindir, "-W", "-f=${db:Status-Abbrev}\t${binary:Package}\t${Version}\n"],
+        "List Packages",
+    )?;
+    Ok(parse_installed_packages(&output))
+}
+
+/// Parses `status<TAB>package<TAB>version` lines from dpkg-query. Only fully
+/// installed (`ii`) packages count; removed ones left in the config-files
+/// state are still listed by `dpkg-query -W`.
+fn parse_installed_packages(output: &str) -> BTreeMap<String, String> {
+    output
+    .lines()
+    .filter_map(|line| {
+        let mut fields = line.split('\t');
+        let (status, name, version) = (fields.next()?, fields.next()?, fields.next()?);
+        status.starts_with("ii").then(|| (name.to_string(), version.trim().to_string()))
+    })
+    .collect()
+}
+
+/// Hex SHA-256 identifying the package set installed at `root`.
+pub fn compute_system_version(root: &Path) -> Result<String> {
+    Ok(hash_package_versions(&installed_packages(root)?))
+}
+
+/// Hashes `name=version` lines in sorted order, so the result only depends on
+/// the package set and not on how dpkg formats its output.
+pub fn hash_package_versions(packages: &BTreeMap<String, String>) -> String {
+    let mut hasher = Sha256::new();
+    for (name, version) in packages {
+        hasher.update(format!("{}={}\n", name, version).as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Collects the active APT source entries of the system tree at `root`, one-line
+/// `.list` entries as-is and deb822 `.sources` stanzas folded onto one line.
+pub fn apt_source_entries(root: &Path) -> BTreeSet<String> {
+    let mut files = vec![root.join(APT_SOURCES_LIST.trim_start_matches('/'))];
+    if let Ok(entries) = fs::read_dir(root.join(APT_SOURCES_DIR.trim_start_matches('/'))) {
+        files.extend(entries.flatten().map(|e| e.path()));
+    }
+
+    let mut result = BTreeSet::new();
+    for file in files {
+        let Ok(content) = fs::read_to_string(&file) else { continue };
+        let active = content
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or("").split_whitespace().collect::<Vec<_>>().join(" "));
+
+        match file.extension().and_then(|e| e.to_str()) {
+            Some("sources") => {
+                let mut stanza = Vec::new();
+                for line in active.chain(std::iter::once(String::new())) {
+                    if line.is_empty() {
+                        if !stanza.is_empty() {
+                            result.insert(stanza.join("; "));
+                            stanza.clear();
+                        }
+                    } else {
+                        stanza.push(line);
+                    }
+                }
+            }
+            Some("list") => result.extend(active.filter(|l| !l.is_empty())),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// True when /boot is its own filesystem, so snapshots of @ do not include it.
+pub fn boot_is_separate() -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata("/"), fs::metadata("/boot")) {
+        (Ok(root), Ok(boot)) => root.dev() != boot.dev(),
+        _ => false,
+    }
+}
+
+/// Copies kernels, initrds and their config/System.map files from `src` to
+/// `dest`. Files already in `dest` are left alone. Returns how many were copied.
+pub fn copy_boot_images(src: &Path, dest: &Path) -> Result<usize> {
+    const PREFIXES: [&str; 4] = ["vmlinuz-", "initrd.img-", "config-", "System.map-"];
+    fs::create_dir_all(dest).into_diagnostic()?;
+
+    let mut copied = 0;
+    for entry in fs::read_dir(src).into_diagnostic()?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_image = PREFIXES.iter().any(|p| name.starts_with(p));
+        if !is_image || !entry.path().is_file() || dest.join(&name).exists() {
+            continue;
+        }
+        fs::copy(entry.path(), dest.join(&name)).into_diagnostic()?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// Copies the live /boot into an existing snapshot's /boot.
+pub fn btrfs_capture_boot(name: &str) -> Result<usize> {
+    mount_btrfs_root()?;
+    let snap_target = Paths::get().snapshots_dir().join(name);
+    let result = if snap_target.exists() {
+        copy_boot_images(Path::new("/boot"), &snap_target.join("boot"))
+    } else {
+        Err(HammerError::BtrfsError(format!("Snapshot {} not found", name)).into())
+    };
+    umount_btrfs_root()?;
+    result
+}
+
+/// Reads VERSION_CODENAME from `<root>/etc/os-release`.
+pub fn system_suite(root: &Path) -> Option<String> {
+    let content = fs::read_to_string(root.join("etc/os-release")).ok()?;
+    content
+    .lines()
+    .find_map(|line| line.strip_prefix("VERSION_CODENAME="))
+    .map(|value| value.trim_matches('"').to_string())
+    .filter(|value| !value.is_empty())
+}
+
+/// Checks that the system tree under `root` can boot: a kernel with a matching
+/// initrd in /boot, a resolvable /sbin/init and a non-empty /etc/fstab.
+/// Returns a description of each problem found.
+pub fn boot_sanity_problems(root: &Path) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let kernels: Vec<String> = fs::read_dir(root.join("boot"))
+    .map(|entries| {
+        entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_string_lossy().strip_prefix("vmlinuz-").map(str::to_string))
+        .collect()
+    })
+    .unwrap_or_default();
+
+    if kernels.is_empty() {
+        problems.push("no /boot/vmlinuz-* kernel found".to_string());
+    } else if !kernels.iter().any(|k| root.join("boot").join(format!("initrd.img-{}", k)).exists()) {
+        problems.push(format!("no initrd.img found for kernel(s) {}", kernels.join(", ")));
+    }
+
+    if resolve_in_root(root, Path::new("/sbin/init")).is_none() {
+        problems.push("/sbin/init does not resolve".to_string());
+    }
+
+    let fstab_empty = fs::read_to_string(root.join("etc/fstab"))
+    .map(|content| content.lines().all(|l| l.trim().is_empty() || l.trim().starts_with('#')))
+    .unwrap_or(true);
+    if fstab_empty {
+        problems.push("/etc/fstab is missing or empty".to_string());
+    }
+
+    problems
+}
+
+/// Follows symlinks for `path` as if `root` were `/`, so absolute link targets
+/// inside a snapshot don't escape into the running system.
+fn resolve_in_root(root: &Path, path: &Path) -> Option<PathBuf> {
+    let mut current = path.to_path_buf();
+    for _ in 0..40 {
+        let host_path = root.join(current.strip_prefix("/").unwrap_or(&current));
+        let metadata = fs::symlink_metadata(&host_path).ok()?;
+        if !metadata.file_type().is_symlink() {
+            return Some(host_path);
+        }
+        let target = fs::read_link(&host_path).ok()?;
+        current = if target.is_absolute() {
+            target
+        } else {
+            current.parent().unwrap_or(Path::new("/")).join(target)
+        };
+    }
+    None
+}
+
 pub fn btrfs_list_atomic_snapshots() -> Result<Vec<String>> {
     mount_btrfs_root()?;
-    let snap_dir = Path::new(MOUNT_POINT).join("@snapshots");
+    let snap_dir = Paths::get().snapshots_dir();
 
     let mut snaps = Vec::new();
     if snap_dir.exists() {
         for entry in fs::read_dir(snap_dir).into_diagnostic()? {
             let entry = entry.into_diagnostic()?;
-            snaps.push(entry.file_name().to_string_lossy().to_string());
+            // Skip the .meta.json sidecars, snapshots are always directories
+            if entry.file_type().into_diagnostic()?.is_dir() {
+                snaps.push(entry.file_name().to_string_lossy().to_string());
+            }
         }
     }
 
@@ -209,14 +997,431 @@ pub fn btrfs_list_atomic_snapshots() -> Result<Vec<String>> {
     Ok(snaps)
 }
 
+/// Lists snapshots together with their metadata. Snapshots taken before
+/// metadata was recorded, or whose sidecar is corrupt, get `None`.
+pub fn btrfs_list_snapshots_with_meta() -> Result<Vec<(String, Option<SnapshotMeta>)>> {
+    let snaps = btrfs_list_atomic_snapshots()?;
+
+    mount_btrfs_root()?;
+    let snap_dir = Paths::get().snapshots_dir();
+    let result = snaps
+    .into_iter()
+    .map(|name| {
+        let meta = fs::read_to_string(snap_meta_path(&snap_dir, &name))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+        (name, meta)
+    })
+    .collect();
+
+    umount_btrfs_root()?;
+    Ok(result)
+}
+
+/// `strftime` format of the timestamp every snapshot name starts with
+pub const SNAPSHOT_NAME_FORMAT: &str = "%Y-%m-%d-%H%M%S";
+
+/// Creation time encoded at the start of a snapshot name.
+pub fn snapshot_timestamp(name: &str) -> Option<chrono::NaiveDateTime> {
+    let prefix = name.get(..17)?;
+    chrono::NaiveDateTime::parse_from_str(prefix, SNAPSHOT_NAME_FORMAT).ok()
+}
+
+/// Human-readable age of a snapshot timestamp, e.g. "2 hours ago".
+pub fn format_age(timestamp: Option<chrono::NaiveDateTime>) -> String {
+    let Some(taken) = timestamp else {
+        return "unknown".to_string();
+    };
+    let age = chrono::Local::now().naive_local() - taken;
+    let (value, unit) = if age.num_minutes() < 1 {
+        return "just now".to_string();
+    } else if age.num_hours() < 1 {
+        (age.num_minutes(), "minute")
+    } else if age.num_days() < 1 {
+        (age.num_hours(), "hour")
+    } else if age.num_weeks() < 5 {
+        (age.num_days(), "day")
+    } else {
+        (age.num_weeks(), "week")
+    };
+    format!("{} {}{} ago", value, unit, if value == 1 { "" } else { "s" })
+}
+
+/// A snapshot in @snapshots with its name timestamp and sidecar metadata.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    /// Location under the top-level mount, only readable while it is mounted
+    pub path: PathBuf,
+    pub timestamp: Option<chrono::NaiveDateTime>,
+    pub meta: Option<SnapshotMeta>,
+}
+
+/// All snapshots, oldest first with unparseable names last, loaded in one pass so callers share the
+/// @snapshots layout and the naming convention instead of re-deriving them.
+pub struct SnapshotStore {
+    snapshots: Vec<Snapshot>,
+}
+
+impl SnapshotStore {
+    pub fn load() -> Result<Self> {
+        let snap_dir = Paths::get().snapshots_dir();
+        let mut snapshots: Vec<Snapshot> = btrfs_list_snapshots_with_meta()?
+        .into_iter()
+        .map(|(name, meta)| Snapshot {
+            path: snap_dir.join(&name),
+            timestamp: snapshot_timestamp(&name),
+            name,
+            meta,
+        })
+        .collect();
+        Ok(Self::sorted(snapshots))
+    }
+
+    /// The snapshots `btrfs subvolume list /` reports, without their metadata.
+    /// Needs no top-level mount, so read-only commands like `status` can't
+    /// unmount it from under a running update.
+    pub fn load_unmounted() -> Result<Self> {
+        let paths = Paths::get();
+        let output = run_command("btrfs", &["subvolume", "list", "/"], "List Subvolumes")?;
+        let snapshots = parse_subvolume_list(&output)
+        .into_iter()
+        .filter(|path| path.parent() == Some(Path::new(&paths.snapshots_subvol)))
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .map(|name| Snapshot {
+            path: paths.snapshots_dir().join(&name),
+            timestamp: snapshot_timestamp(&name),
+            name,
+            meta: None,
+        })
+        .collect();
+        Ok(Self::sorted(snapshots))
+    }
+
+    fn sorted(mut snapshots: Vec<Snapshot>) -> Self {
+        snapshots.sort_by(|a, b| {
+            (a.timestamp.is_none(), a.timestamp, &a.name).cmp(&(b.timestamp.is_none(), b.timestamp, &b.name))
+        });
+        Self { snapshots }
+    }
+
+    pub fn list(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Snapshots whose name carries a timestamp, oldest first. Retention and
+    /// "n steps back" count only these, since the age of the rest is unknown.
+    pub fn dated(&self) -> &[Snapshot] {
+        let end = self.snapshots.iter().position(|s| s.timestamp.is_none()).unwrap_or(self.snapshots.len());
+        &self.snapshots[..end]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Snapshot> {
+        self.snapshots.iter().find(|s| s.name == name)
+    }
+
+    /// The newest snapshot, i.e. the most recent restore point.
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.dated().last()
+    }
+
+    /// The snapshot taken just before `snapshot`.
+    pub fn parent_of(&self, snapshot: &Snapshot) -> Option<&Snapshot> {
+        let dated = self.dated();
+        let index = dated.iter().position(|s| s.name == snapshot.name)?;
+        index.checked_sub(1).map(|i| &dated[i])
+    }
+}
+
 pub fn btrfs_delete_atomic_snapshot(name: &str) -> Result<()> {
     mount_btrfs_root()?;
-    let snap_path = Path::new(MOUNT_POINT).join("@snapshots").join(name);
+    let snap_path = Paths::get().snapshots_dir().join(name);
 
     if snap_path.exists() {
-        run_command("btrfs", &["subvolume", "delete", &snap_path.to_string_lossy()], "Delete Snapshot")?;
+        btrfs_delete_tree(&snap_path)?;
+    }
+
+    let meta_path = snap_meta_path(&Paths::get().snapshots_dir(), name);
+    if meta_path.exists() {
+        fs::remove_file(meta_path).into_diagnostic()?;
     }
 
     umount_btrfs_root()?;
     Ok(())
 }
+
+/// Empties /var/log inside a snapshot. Rotated and journal files are deleted,
+/// live log files are truncated so their ownership and modes survive a rollback.
+/// Returns the number of bytes reclaimed.
+pub fn btrfs_vacuum_snapshot_logs(name: &str) -> Result<u64> {
+    mount_btrfs_root()?;
+    let snap_path = Paths::get().snapshots_dir().join(name);
+
+    let result = if snap_path.exists() {
+        vacuum_logs_in(&snap_path)
+    } else {
+        Err(HammerError::BtrfsError(format!("Snapshot {} not found", name)).into())
+    };
+
+    umount_btrfs_root()?;
+    result
+}
+
+fn vacuum_logs_in(root: &Path) -> Result<u64> {
+    let log_dir = root.join("var/log");
+    if !log_dir.exists() {
+        return Ok(0);
+    }
+
+    // Never touch the running system's logs, only the snapshot tree
+    let resolved = fs::canonicalize(&log_dir).into_diagnostic()?;
+    if !resolved.starts_with(&Paths::get().mount_point) {
+        return Err(HammerError::BtrfsError(format!(
+            "Refusing to vacuum logs outside the snapshot tree: {}",
+            resolved.display()
+        )).into());
+    }
+
+    let before = calculate_dir_size(&resolved)?;
+
+    // Symlinks are not followed, so a link pointing into the live system is left alone
+    for entry in WalkDir::new(&resolved) {
+        let entry = entry.into_diagnostic()?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let file_name = entry.file_name().to_string_lossy();
+        if is_disposable_log(&file_name) {
+            fs::remove_file(entry.path()).into_diagnostic()?;
+        } else {
+            OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(entry.path())
+            .into_diagnostic()?;
+        }
+    }
+
+    let after = calculate_dir_size(&resolved)?;
+    Ok(before.saturating_sub(after))
+}
+
+fn is_disposable_log(file_name: &str) -> bool {
+    let Some((_, ext)) = file_name.rsplit_once('.') else {
+        return false;
+    };
+    matches!(ext, "gz" | "xz" | "bz2" | "zst" | "old" | "journal" | "journal~")
+    || (!ext.is_empty() && ext.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hammer-core-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn subvolume_list_reads_nested_paths() {
+        // `btrfs subvolume list -p -u` adds columns before the path
+        let output = "\
+ID 256 gen 1204 top level 5 path @
+ID 257 gen 1198 parent 5 top level 5 parent_uuid - uuid 0f6c path @home
+ID 262 gen 1180 parent 256 top level 256 parent_uuid - uuid 9a1e path @/var/lib/machines
+ID 270 gen 1199 top level 5 path @snapshots/2024-03-01-000005-update
+";
+        assert_eq!(
+            parse_subvolume_list(output),
+            ["@", "@home", "@/var/lib/machines", "@snapshots/2024-03-01-000005-update"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn subvolume_list_handles_fs_tree_spaces_and_noise() {
+        // `btrfs subvolume list -a` output, with a line btrfs doesn't normally print
+        let output = "\
+ID 256 gen 1204 top level 5 path <FS_TREE>/@
+ID 263 gen 1201 top level 256 path <FS_TREE>/@/opt/my path dir
+WARNING: cannot read default subvolume id
+
+ID 264 gen 1202 top level 5 path <FS_TREE>/@snapshots/2024-03-01-000005-update
+";
+        assert_eq!(
+            parse_subvolume_list(output),
+            ["@", "@/opt/my path dir", "@snapshots/2024-03-01-000005-update"].map(PathBuf::from)
+        );
+        assert!(parse_subvolume_list("").is_empty());
+    }
+
+    fn store(names: &[&str]) -> SnapshotStore {
+        SnapshotStore::sorted(
+            names
+            .iter()
+            .map(|name| Snapshot {
+                name: name.to_string(),
+                path: PathBuf::from("/tmp").join(name),
+                timestamp: snapshot_timestamp(name),
+                meta: None,
+            })
+            .collect(),
+        )
+    }
+
+    #[test]
+    fn snapshots_sort_across_day_boundary() {
+        let store = store(&[
+            "2024-03-01-000005-update",
+            "imported-root",
+            "2024-02-29-235959-manual",
+            "2024-03-01-000005-pre-rollback",
+            "2023-12-31-235959-update",
+        ]);
+        let names: Vec<&str> = store.list().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, [
+            "2023-12-31-235959-update",
+            "2024-02-29-235959-manual",
+            "2024-03-01-000005-pre-rollback",
+            "2024-03-01-000005-update",
+            "imported-root",
+        ]);
+        assert_eq!(store.dated().len(), 4);
+        assert_eq!(store.latest().map(|s| s.name.as_str()), Some("2024-03-01-000005-update"));
+        let newest = store.get("2024-03-01-000005-pre-rollback").unwrap();
+        assert_eq!(store.parent_of(newest).map(|s| s.name.as_str()), Some("2024-02-29-235959-manual"));
+    }
+
+    #[test]
+    fn no_escape_codes_when_colour_is_off() {
+        let render = || format!("{}", "live system".if_supports_color(Stream::Stdout, |t| t.green().bold()));
+        Logger::set_color(false);
+        assert_eq!(render(), "live system");
+        Logger::set_color(true);
+        assert!(render().contains('\x1b'));
+    }
+
+    #[test]
+    fn dir_size_counts_files_and_skips_links() {
+        let dir = temp_dir("dir-size");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a"), [0; 10]).unwrap();
+        fs::write(dir.join("sub/b"), [0; 5]).unwrap();
+        std::os::unix::fs::symlink(dir.join("a"), dir.join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.join("gone"), dir.join("dangling")).unwrap();
+
+        assert_eq!(calculate_dir_size(&dir).unwrap(), 15);
+        assert_eq!(calculate_dir_size_filtered(&dir, |p| p.ends_with("sub")).unwrap(), 10);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotate_shifts_logs_and_drops_oldest() {
+        let dir = temp_dir("rotate");
+        fs::write(dir.join("hammer.log"), "current").unwrap();
+        for n in 1..=LOG_ROTATE_KEEP {
+            fs::write(dir.join(format!("hammer.log.{}", n)), n.to_string()).unwrap();
+        }
+
+        Logger::rotate(&dir);
+
+        assert!(!dir.join("hammer.log").exists());
+        assert_eq!(fs::read_to_string(dir.join("hammer.log.1")).unwrap(), "current");
+        for n in 2..=LOG_ROTATE_KEEP {
+            assert_eq!(fs::read_to_string(dir.join(format!("hammer.log.{}", n))).unwrap(), (n - 1).to_string());
+        }
+        assert!(!dir.join(format!("hammer.log.{}", LOG_ROTATE_KEEP + 1)).exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rotate_fills_gaps() {
+        let dir = temp_dir("rotate-gaps");
+        fs::write(dir.join("hammer.log"), "current").unwrap();
+        fs::write(dir.join("hammer.log.2"), "2").unwrap();
+
+        Logger::rotate(&dir);
+
+        assert_eq!(fs::read_to_string(dir.join("hammer.log.1")).unwrap(), "current");
+        assert!(!dir.join("hammer.log.2").exists());
+        assert_eq!(fs::read_to_string(dir.join("hammer.log.3")).unwrap(), "2");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// A root that boots: kernel and initrd, /sbin/init through the usr-merge symlink, an fstab
+    fn bootable_root(name: &str) -> PathBuf {
+        let root = temp_dir(name);
+        fs::create_dir_all(root.join("boot")).unwrap();
+        fs::create_dir_all(root.join("usr/sbin")).unwrap();
+        fs::create_dir_all(root.join("usr/lib/systemd")).unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("boot/vmlinuz-6.1.0-10-amd64"), "kernel").unwrap();
+        fs::write(root.join("boot/initrd.img-6.1.0-10-amd64"), "initrd").unwrap();
+        fs::write(root.join("usr/lib/systemd/systemd"), "init").unwrap();
+        std::os::unix::fs::symlink("/usr/lib/systemd/systemd", root.join("usr/sbin/init")).unwrap();
+        std::os::unix::fs::symlink("usr/sbin", root.join("sbin")).unwrap();
+        fs::write(root.join("etc/fstab"), "# <file system> <mount point>\nUUID=1234 / btrfs subvol=@ 0 0\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn boot_sanity_accepts_complete_root() {
+        let root = bootable_root("boot-ok");
+        assert!(boot_sanity_problems(&root).is_empty(), "{:?}", boot_sanity_problems(&root));
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn boot_sanity_reports_missing_initrd() {
+        let root = bootable_root("boot-no-initrd");
+        fs::remove_file(root.join("boot/initrd.img-6.1.0-10-amd64")).unwrap();
+        assert_eq!(boot_sanity_problems(&root), ["no initrd.img found for kernel(s) 6.1.0-10-amd64"]);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn boot_sanity_does_not_follow_links_out_of_root() {
+        let root = bootable_root("boot-escape");
+        fs::remove_file(root.join("usr/lib/systemd/systemd")).unwrap();
+        fs::write(root.join("etc/fstab"), "# only comments\n\n").unwrap();
+        // The host's /usr/lib/systemd/systemd must not make the snapshot's dangling link resolve
+        assert_eq!(boot_sanity_problems(&root), ["/sbin/init does not resolve", "/etc/fstab is missing or empty"]);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    fn packages(list: &[(&str, &str)]) -> BTreeMap<String, String> {
+        list.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn package_hash_is_stable() {
+        let a = packages(&[("bash", "5.2.15-2"), ("libc6:amd64", "2.36-9")]);
+        let b = packages(&[("libc6:amd64", "2.36-9"), ("bash", "5.2.15-2")]);
+        assert_eq!(hash_package_versions(&a), hash_package_versions(&b));
+        assert_eq!(hash_package_versions(&a).len(), 64);
+    }
+
+    #[test]
+    fn package_hash_changes_with_versions() {
+        let before = packages(&[("bash", "5.2.15-2"), ("libc6:amd64", "2.36-9")]);
+        let upgraded = packages(&[("bash", "5.2.15-2"), ("libc6:amd64", "2.36-9+deb12u1")]);
+        let removed = packages(&[("bash", "5.2.15-2")]);
+        assert_ne!(hash_package_versions(&before), hash_package_versions(&upgraded));
+        assert_ne!(hash_package_versions(&before), hash_package_versions(&removed));
+    }
+
+    #[test]
+    fn installed_packages_skip_removed_and_keep_arches() {
+        let output = "ii \tbash\t5.2.15-2\n\
+                      ii \tlibc6:amd64\t2.36-9\n\
+                      ii \tlibc6:i386\t2.36-9\n\
+                      rc \tnano\t7.2-1\n\
+                      iU \tvim\t2:9.0.1378-2\n";
+        let parsed = parse_installed_packages(output);
+        assert_eq!(parsed.keys().collect::<Vec<_>>(), ["bash", "libc6:amd64", "libc6:i386"]);
+        assert_eq!(parsed["libc6:i386"], "2.36-9");
+    }
+}
@@ -1,11 +1,15 @@
 use miette::{miette, IntoDiagnostic, Result, WrapErr};
 use clap::{Parser, Subcommand};
-use hammer_core::{run_command, Logger};
+use hammer_core::{
+    btrfs_snapshot_atomic, mount_btrfs_root, run_command, run_command_status, umount_btrfs_root,
+    Logger, Paths, SNAPSHOT_NAME_FORMAT,
+};
 use nix::unistd::Uid;
-use owo_colors::OwoColorize;
+use owo_colors::{AnsiColors, OwoColorize, Stream};
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
 #[derive(Parser)]
 #[command(name = "hammer-read")]
@@ -24,32 +28,59 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Lock the system (Read-Only for /usr and /boot)
+    /// Lock the system (Read-Only for /usr, /boot or HAMMER_READONLY_PATHS)
     Lock,
-    /// Unlock the system (Read-Write for /usr and /boot)
+    /// Unlock the system (Read-Write for the protected paths)
     Unlock,
     /// Create a temporary writable overlay on /usr (changes vanish after reboot)
     TemporaryUnlock,
+    /// Drop the temporary overlay on /usr, discarding the changes made in it
+    #[command(alias = "overlay-drop")]
+    TemporaryLock {
+        /// Also unmount and remove the tmpfs holding the overlay's upper layer
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Copy the changes made in the temporary overlay into the real /usr
+    OverlayCommit {
+        /// Only list what would be committed
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Install persistence (Systemd service + fstab RO enforcement + /home setup)
-    Install,
+    Install {
+        /// Print the systemd unit instead of installing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show whether the protected paths are currently locked (no root needed)
+    Status,
 }
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Status only reads /proc and systemd state
+    if let Some(Commands::Status) = cli.command {
+        return print_status();
+    }
+
     if !Uid::current().is_root() {
-        eprintln!("{}", "Permission denied. Must be root.".red().bold());
+        eprintln!("{}", "Permission denied. Must be root.".if_supports_color(Stream::Stderr, |t| t.red().bold()));
         std::process::exit(1);
     }
 
     // Init logger for fancy output
     Logger::init()?;
 
-    let cli = Cli::parse();
-
     match cli.command {
-        Some(Commands::Install) => install_persistence()?,
+        Some(Commands::Install { dry_run }) => install_persistence(dry_run)?,
         Some(Commands::Lock) => toggle_lock(true)?,
         Some(Commands::Unlock) => toggle_lock(false)?,
         Some(Commands::TemporaryUnlock) => enable_overlay_fs()?,
+        Some(Commands::TemporaryLock { purge }) => disable_overlay_fs(purge)?,
+        Some(Commands::OverlayCommit { dry_run }) => commit_overlay_fs(dry_run)?,
+        Some(Commands::Status) => unreachable!(),
         None => {
             if cli.unlock {
                 toggle_lock(false)?;
@@ -62,14 +93,25 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// OS binaries plus kernel and bootloader config
+const DEFAULT_PROTECTED_PATHS: &[&str] = &["/usr", "/boot"];
+
+/// Paths lock/unlock act on: the colon-separated `HAMMER_READONLY_PATHS`, or
+/// `DEFAULT_PROTECTED_PATHS` when it is unset or empty.
+fn protected_paths() -> Vec<String> {
+    std::env::var("HAMMER_READONLY_PATHS")
+    .ok()
+    .map(|v| v.split(':').filter(|p| !p.is_empty()).map(|p| p.to_string()).collect::<Vec<_>>())
+    .filter(|paths| !paths.is_empty())
+    .unwrap_or_else(|| DEFAULT_PROTECTED_PATHS.iter().map(|p| p.to_string()).collect())
+}
+
 fn toggle_lock(readonly: bool) -> Result<()> {
     Logger::section("Filesystem Protection");
 
-    // Protect OS binaries
-    remount_path_via_bind("/usr", readonly)?;
-
-    // Protect Kernel and Bootloader config
-    remount_path_via_bind("/boot", readonly)?;
+    for path in protected_paths() {
+        remount_path_via_bind(&path, readonly)?;
+    }
 
     Logger::end_section();
     Ok(())
@@ -81,41 +123,160 @@ fn toggle_lock(readonly: bool) -> Result<()> {
 fn remount_path_via_bind(path: &str, readonly: bool) -> Result<()> {
     let target = Path::new(path);
     if !target.exists() {
+        Logger::warn(&format!("{} does not exist, skipping.", path));
         return Ok(());
     }
 
-    // Check if it is already a mountpoint
-    let check_mount = run_command("mountpoint", &["-q", path], "Check Mountpoint");
-
     // If not a mountpoint, bind mount it to itself to make it one
-    if check_mount.is_err() {
+    if !is_mountpoint(path)? {
         Logger::info(&format!("Converting {} to bind mount...", path));
         run_command("mount", &["--bind", path, path], "Bind Mount Self")?;
     }
 
+    let opts = remount_options(&current_mount_flags(path), readonly);
+
     if readonly {
         Logger::info(&format!("Locking {} (Read-Only)...", path));
         // Note: remount,bind,ro is the correct sequence to change flags on a bind mount
-        run_command("mount", &["-o", "remount,bind,ro", path], "Remount RO")?;
+        run_command("mount", &["-o", &opts, path], "Remount RO")?;
     } else {
         Logger::info(&format!("Unlocking {} (Read-Write)...", path));
-        run_command("mount", &["-o", "remount,bind,rw", path], "Remount RW")?;
+        run_command("mount", &["-o", &opts, path], "Remount RW")?;
     }
 
     Logger::success(&format!("{} configured.", path));
     Ok(())
 }
 
+/// `mount -o` options switching a bind mount to ro or rw. A bind remount
+/// replaces all per-mount flags, so `preserved` (nosuid, nodev, ...) is repeated.
+fn remount_options(preserved: &[String], readonly: bool) -> String {
+    let mode = if readonly { "ro" } else { "rw" };
+    if preserved.is_empty() {
+        format!("remount,bind,{}", mode)
+    } else {
+        format!("remount,bind,{},{}", mode, preserved.join(","))
+    }
+}
+
+/// `mountpoint -q` exits 0 for a mount point and 32 for a plain directory;
+/// any other code means the check itself failed.
+fn is_mountpoint(path: &str) -> Result<bool> {
+    let output = run_command_status("mountpoint", &["-q", path])?;
+    match output.code {
+        Some(0) => Ok(true),
+        Some(32) => Ok(false),
+        _ => Err(miette!("Checking whether {} is a mount point failed: {}", path, output.stderr.trim())),
+    }
+}
+
+/// Per-mount flags a bind remount would otherwise drop.
+const PRESERVED_MOUNT_FLAGS: &[&str] = &[
+    "nosuid", "nodev", "noexec", "noatime", "nodiratime", "relatime", "strictatime",
+];
+
+/// Reads the current per-mount flags of `path` from /proc/self/mountinfo,
+/// keeping only those listed in `PRESERVED_MOUNT_FLAGS`.
+fn current_mount_flags(path: &str) -> Vec<String> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    parse_mount_flags(&mountinfo, path)
+}
+
+/// Undoes the octal escapes mountinfo uses for space, tab, newline and
+/// backslash. Decoding to bytes first keeps multibyte names intact.
+fn unescape_mountinfo(field: &str) -> String {
+    let raw = field.as_bytes();
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let octal = raw.get(i + 1..i + 4).filter(|d| raw[i] == b'\\' && d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match octal {
+            Some(digits) => {
+                bytes.push(digits.iter().fold(0u8, |acc, d| acc.wrapping_mul(8).wrapping_add(d - b'0')));
+                i += 4;
+            }
+            None => {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the per-mount options and filesystem type of the mount visible at
+/// `path`, or None if `path` is not a mount point.
+fn visible_mount(mountinfo: &str, path: &str) -> Option<(String, String)> {
+    // Later lines stack on top of earlier ones, so the last match is what's visible
+    mountinfo
+    .lines()
+    .filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let mount_point = unescape_mountinfo(fields.get(4)?);
+        if mount_point != path {
+            return None;
+        }
+        // Optional fields end with a lone "-", followed by the filesystem type
+        let sep = fields.iter().position(|f| *f == "-")?;
+        Some((fields.get(5)?.to_string(), fields.get(sep + 1)?.to_string()))
+    })
+    .next_back()
+}
+
+fn parse_mount_flags(mountinfo: &str, path: &str) -> Vec<String> {
+    let options = visible_mount(mountinfo, path).map(|(opts, _)| opts).unwrap_or_default();
+
+    options
+    .split(',')
+    .filter(|opt| PRESERVED_MOUNT_FLAGS.contains(opt))
+    .map(|opt| opt.to_string())
+    .collect()
+}
+
+fn print_status() -> Result<()> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+    .into_diagnostic()
+    .wrap_err("Failed to read mount table")?;
+
+    println!("{}", "Filesystem Protection".if_supports_color(Stream::Stdout, |t| t.bold()));
+    for path in protected_paths() {
+        let path = path.as_str();
+        let (state, color) = match visible_mount(&mountinfo, path) {
+            Some((_, fstype)) if fstype == "overlay" => ("temporary overlay (writable)", AnsiColors::Yellow),
+            Some((opts, _)) if opts.split(',').any(|o| o == "ro") => ("locked (read-only)", AnsiColors::Green),
+            Some(_) => ("unlocked (read-write)", AnsiColors::Red),
+            None if Path::new(path).exists() => ("unlocked (not a mount point)", AnsiColors::Red),
+            None => ("not present", AnsiColors::BrightBlack),
+        };
+        println!("   {: <8} {}", path, state.if_supports_color(Stream::Stdout, |t| t.color(color)));
+    }
+
+    // is-enabled exits non-zero for disabled/missing units, which isn't an error here
+    let enabled = run_command_status("systemctl", &["is-enabled", "hammer-readonly.service"])
+    .map(|out| out.stdout.trim().to_string())
+    .unwrap_or_default();
+    let (service, color) = match enabled.as_str() {
+        "enabled" => ("enabled", AnsiColors::Green),
+        "" => ("not installed", AnsiColors::Red),
+        other => (other, AnsiColors::Red),
+    };
+    println!("   {: <8} {}", "service", service.if_supports_color(Stream::Stdout, |t| t.color(color)));
+    Ok(())
+}
+
+/// tmpfs holding the upper and work dirs of the temporary /usr overlay
+const OVERLAY_BASE: &str = "/run/hammer/overlay";
+
 fn enable_overlay_fs() -> Result<()> {
     Logger::section("Temporary Overlay");
     Logger::info("Setting up OverlayFS for temporary write access...");
 
     // 1. Prepare tmpfs for upper/work dirs
-    let overlay_base = Path::new("/run/hammer/overlay");
+    let overlay_base = Path::new(OVERLAY_BASE);
     if !overlay_base.exists() {
         fs::create_dir_all(overlay_base).into_diagnostic()?;
         // Mount tmpfs
-        run_command("mount", &["-t", "tmpfs", "tmpfs", "/run/hammer/overlay", "-o", "size=1G"], "Mount Tmpfs")?;
+        run_command("mount", &["-t", "tmpfs", "tmpfs", OVERLAY_BASE, "-o", "size=1G"], "Mount Tmpfs")?;
     }
 
     let upper_dir = overlay_base.join("upper");
@@ -139,7 +300,197 @@ fn enable_overlay_fs() -> Result<()> {
     Ok(())
 }
 
-fn install_persistence() -> Result<()> {
+fn disable_overlay_fs(purge: bool) -> Result<()> {
+    Logger::section("Temporary Overlay");
+
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").into_diagnostic()?;
+    if !matches!(visible_mount(&mountinfo, "/usr"), Some((_, fstype)) if fstype == "overlay") {
+        return Err(miette!("No temporary overlay is mounted on /usr."));
+    }
+
+    Logger::warn("Changes made to /usr through the overlay will no longer be visible.");
+    run_command("umount", &["/usr"], "Unmount Overlay")
+    .wrap_err("/usr is busy; close programs running from it and retry")?;
+    Logger::success("Overlay removed, /usr is back to its previous state.");
+
+    if !purge {
+        Logger::info(&format!("The upper layer stays in {} until reboot; --purge discards it now.", OVERLAY_BASE));
+    } else if Path::new(OVERLAY_BASE).exists() {
+        if visible_mount(&mountinfo, OVERLAY_BASE).is_some() {
+            run_command("umount", &[OVERLAY_BASE], "Unmount Tmpfs")?;
+        }
+        fs::remove_dir_all(OVERLAY_BASE).into_diagnostic()?;
+        Logger::info(&format!("Removed {}.", OVERLAY_BASE));
+    }
+
+    Logger::end_section();
+    Ok(())
+}
+
+/// A change recorded in the overlay's upper layer, relative to /usr.
+enum OverlayChange {
+    Write(PathBuf),
+    Delete(PathBuf),
+    /// An opaque directory, which hides everything below it in the lower /usr
+    Replace(PathBuf),
+}
+
+/// Lists the files written or deleted through the overlay. Directories are
+/// implied by their contents unless opaque (a directory removed and created
+/// again); whiteouts (0:0 character devices) mark deletions.
+fn overlay_changes(upper_dir: &Path) -> Result<Vec<OverlayChange>> {
+    let output = run_command(
+        "getfattr",
+        &["-R", "-P", "--absolute-names", "-d", "-m", "^trusted\\.overlay\\.opaque$", &upper_dir.to_string_lossy()],
+        "Read Overlay Attributes",
+    )?;
+    let opaque = opaque_dirs(&output, upper_dir);
+
+    let mut changes = Vec::new();
+    for entry in WalkDir::new(upper_dir).min_depth(1) {
+        let entry = entry.into_diagnostic()?;
+        let rel = entry.path().strip_prefix(upper_dir).into_diagnostic()?.to_path_buf();
+        let meta = entry.path().symlink_metadata().into_diagnostic()?;
+        if meta.file_type().is_char_device() && meta.rdev() == 0 {
+            changes.push(OverlayChange::Delete(rel));
+        } else if meta.is_dir() {
+            if opaque.contains(&rel) {
+                changes.push(OverlayChange::Replace(rel));
+            }
+        } else {
+            changes.push(OverlayChange::Write(rel));
+        }
+    }
+    Ok(changes)
+}
+
+/// Directories `getfattr -d` output marks opaque (`trusted.overlay.opaque="y"`),
+/// relative to `upper_dir`. getfattr escapes names the way mountinfo does.
+fn opaque_dirs(output: &str, upper_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut file = None;
+    for line in output.lines() {
+        if let Some(path) = line.strip_prefix("# file: ") {
+            file = Some(PathBuf::from(unescape_mountinfo(path)));
+        } else if line == "trusted.overlay.opaque=\"y\"" {
+            if let Some(rel) = file.as_deref().and_then(|f| f.strip_prefix(upper_dir).ok()) {
+                dirs.push(rel.to_path_buf());
+            }
+        }
+    }
+    dirs
+}
+
+/// Persists the overlay's changes into /usr of the root subvolume, after
+/// taking a restore point. The overlay stays mounted; since it now matches
+/// the lower /usr, dropping it or rebooting shows the same files.
+fn commit_overlay_fs(dry_run: bool) -> Result<()> {
+    Logger::section("Commit Overlay");
+
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").into_diagnostic()?;
+    if !matches!(visible_mount(&mountinfo, "/usr"), Some((_, fstype)) if fstype == "overlay") {
+        return Err(miette!("No temporary overlay is mounted on /usr."));
+    }
+
+    let upper_dir = Path::new(OVERLAY_BASE).join("upper");
+    let changes = overlay_changes(&upper_dir)?;
+    if changes.is_empty() {
+        Logger::info("The overlay holds no changes.");
+        Logger::end_section();
+        return Ok(());
+    }
+
+    for change in &changes {
+        match change {
+            OverlayChange::Write(rel) => Logger::info(&format!("  {} /usr/{}", "+".if_supports_color(Stream::Stdout, |t| t.green()), rel.display())),
+            OverlayChange::Delete(rel) => Logger::info(&format!("  {} /usr/{}", "-".if_supports_color(Stream::Stdout, |t| t.red()), rel.display())),
+            OverlayChange::Replace(rel) => Logger::info(&format!(
+                "  {} /usr/{}/ (replaced)",
+                "~".if_supports_color(Stream::Stdout, |t| t.yellow()),
+                rel.display()
+            )),
+        }
+    }
+
+    if dry_run {
+        Logger::info(&format!("[dry-run] {} change(s) would be committed.", changes.len()));
+        Logger::end_section();
+        return Ok(());
+    }
+
+    let snap_name = format!("{}-pre-overlay-commit", chrono::Local::now().format(SNAPSHOT_NAME_FORMAT));
+    Logger::info(&format!("Creating restore point {}...", snap_name));
+    btrfs_snapshot_atomic(&snap_name, "pre-overlay-commit")?;
+
+    mount_btrfs_root()?;
+    let target = Paths::get().root_subvol_path().join("usr");
+    let result = apply_overlay_changes(&upper_dir, &target, &changes);
+    umount_btrfs_root()?;
+    result?;
+
+    Logger::success(&format!("Committed {} change(s) to /usr. Restore point: {}", changes.len(), snap_name));
+    Logger::end_section();
+    Ok(())
+}
+
+fn apply_overlay_changes(upper_dir: &Path, target: &Path, changes: &[OverlayChange]) -> Result<()> {
+    // The overlay showed none of the lower contents of an opaque directory, so neither may /usr
+    clear_replaced_dirs(target, changes)?;
+
+    // Whiteouts are skipped by rsync and applied as deletions below. The
+    // overlay's own xattrs would mark files in the real /usr as opaque.
+    let src = format!("{}/", upper_dir.display());
+    let dest = format!("{}/", target.display());
+    run_command(
+        "rsync",
+        &["-aHAX", "--no-devices", "--filter=-x trusted.overlay.*", &src, &dest],
+        "Copy Overlay Files",
+    )?;
+
+    for change in changes {
+        if let OverlayChange::Delete(rel) = change {
+            let path = target.join(rel);
+            let meta = match path.symlink_metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            if meta.is_dir() {
+                fs::remove_dir_all(&path).into_diagnostic()?;
+            } else {
+                fs::remove_file(&path).into_diagnostic()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes what `target` holds at each opaque directory, parents first so
+/// nested ones are already gone.
+fn clear_replaced_dirs(target: &Path, changes: &[OverlayChange]) -> Result<()> {
+    for change in changes {
+        if let OverlayChange::Replace(rel) = change {
+            let path = target.join(rel);
+            match path.symlink_metadata() {
+                Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path).into_diagnostic()?,
+                Ok(_) => fs::remove_file(&path).into_diagnostic()?,
+                Err(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Installed location of this binary; the boot-time unit calls it directly
+const READ_BIN: &str = "/usr/lib/HackerOS/hammer/bin/hammer-read";
+const SERVICE_PATH: &str = "/etc/systemd/system/hammer-readonly.service";
+
+fn install_persistence(dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("# {}", SERVICE_PATH);
+        print!("{}", service_unit());
+        return Ok(());
+    }
+
     Logger::section("Installing Persistence");
     install_systemd_service()?;
     update_fstab()?;
@@ -149,27 +500,39 @@ fn install_persistence() -> Result<()> {
     Ok(())
 }
 
+fn service_unit() -> String {
+    // The unit runs without our environment, so pin the current path list into it
+    format!(
+r#"[Unit]
+Description=Hammer Read-Only Enforcement
+DefaultDependencies=no
+After=systemd-remount-fs.service
+Before=local-fs.target
+
+[Service]
+Type=oneshot
+ExecStart={} lock
+RemainAfterExit=yes
+StandardOutput=journal
+Environment=HAMMER_READONLY_PATHS={}
+
+[Install]
+WantedBy=sysinit.target
+"#,
+        READ_BIN,
+        protected_paths().join(":")
+    )
+}
+
 fn install_systemd_service() -> Result<()> {
     Logger::info("Installing hammer-readonly systemd service...");
 
-    let service_content = r#"[Unit]
-    Description=Hammer Read-Only Enforcement
-    DefaultDependencies=no
-    After=systemd-remount-fs.service
-    Before=local-fs.target
-
-    [Service]
-    Type=oneshot
-    ExecStart=/usr/bin/hammer read-only lock
-    RemainAfterExit=yes
-    StandardOutput=journal
-
-    [Install]
-    WantedBy=sysinit.target
-    "#;
+    // An ExecStart that doesn't exist would only show up as a failed unit at boot
+    if !Path::new(READ_BIN).is_file() {
+        return Err(miette!("{} not found; install hammer before enabling the service.", READ_BIN));
+    }
 
-    let service_path = "/etc/systemd/system/hammer-readonly.service";
-    fs::write(service_path, service_content)
+    fs::write(SERVICE_PATH, service_unit())
     .into_diagnostic()
     .wrap_err("Failed to write service file")?;
 
@@ -192,36 +555,34 @@ fn update_fstab() -> Result<()> {
     let mut modified = false;
 
     for line in content.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            new_lines.push(line.to_string());
-            continue;
-        }
-
-        let parts: Vec<&str> = trimmed.split_whitespace().collect();
-        if parts.len() >= 4 {
-            let mount_point = parts[1];
-            let options = parts[3];
-
-            if mount_point == "/boot" && !options.contains("ro") {
-                let new_opts = replace_option(options, "rw", "ro");
-                new_lines.push(reconstruct_fstab_line(&parts, &new_opts));
-                modified = true;
-                continue;
-            }
-            // Ensure @home is RW if using btrfs
-            if mount_point == "/home" && !options.contains("rw") && !options.contains("defaults") {
-                let new_opts = replace_option(options, "ro", "rw");
-                new_lines.push(reconstruct_fstab_line(&parts, &new_opts));
-                modified = true;
+        let parts = match fstab_fields(line) {
+            Some(parts) if parts.len() >= 4 => parts,
+            _ => {
+                new_lines.push(line.to_string());
                 continue;
             }
+        };
+        let mount_point = parts[1];
+        let options = parts[3];
+
+        if mount_point == "/boot" && !has_option(options, "ro") {
+            let new_opts = replace_option(options, "rw", "ro");
+            new_lines.push(reconstruct_fstab_line(&parts, &new_opts));
+            modified = true;
+            continue;
+        }
+        // Ensure @home is RW if using btrfs
+        if mount_point == "/home" && has_option(options, "ro") {
+            let new_opts = replace_option(options, "ro", "rw");
+            new_lines.push(reconstruct_fstab_line(&parts, &new_opts));
+            modified = true;
+            continue;
         }
         new_lines.push(line.to_string());
     }
 
     if modified {
-        fs::write(format!("{}.bak", fstab_path), &content).into_diagnostic()?;
+        backup_fstab(&content)?;
         fs::write(fstab_path, new_lines.join("\n") + "\n").into_diagnostic()?;
         Logger::success("fstab updated.");
     } else {
@@ -233,19 +594,21 @@ fn update_fstab() -> Result<()> {
 
 fn ensure_home_persistence() -> Result<()> {
     let home_path = Path::new("/home");
-    // Check if /home is a mountpoint
-    let check = run_command("mountpoint", &["-q", "/home"], "Check Home");
-
-    if check.is_err() {
+    if !is_mountpoint("/home")? {
         // If not a mountpoint, maybe we need to bind mount /var/home
         Logger::info("/home is not a mountpoint. Setting up /var/home bind...");
         let var_home = Path::new("/var/home");
         if !var_home.exists() {
             fs::create_dir_all(var_home).into_diagnostic()?;
         }
-        // Add bind mount to fstab if not present
+        // Add bind mount to fstab unless some entry already mounts /home
         let fstab = fs::read_to_string("/etc/fstab").into_diagnostic()?;
-        if !fstab.contains("/var/home /home") {
+        let home_mounted = fstab
+        .lines()
+        .filter_map(fstab_fields)
+        .any(|parts| parts.get(1) == Some(&"/home"));
+        if !home_mounted {
+            backup_fstab(&fstab)?;
             let bind_entry = "/var/home /home none defaults,bind 0 0";
             let mut file = fs::OpenOptions::new().append(true).open("/etc/fstab").into_diagnostic()?;
             use std::io::Write;
@@ -257,12 +620,42 @@ fn ensure_home_persistence() -> Result<()> {
     Ok(())
 }
 
+/// Splits an fstab entry into its fields, or None for blank and comment lines.
+fn fstab_fields(line: &str) -> Option<Vec<&str>> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+    Some(trimmed.split_whitespace().collect())
+}
+
+/// Saves `content` as /etc/fstab.bak unless a backup is already there, so the
+/// fstab from before hammer first edited it is never overwritten by a later edit.
+fn backup_fstab(content: &str) -> Result<()> {
+    match fs::OpenOptions::new().write(true).create_new(true).open("/etc/fstab.bak") {
+        Ok(mut file) => {
+            use std::io::Write;
+            file.write_all(content.as_bytes()).into_diagnostic().wrap_err("Failed to back up fstab")
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e).into_diagnostic().wrap_err("Failed to back up fstab"),
+    }
+}
+
+fn has_option(options: &str, option: &str) -> bool {
+    options.split(',').any(|opt| opt == option)
+}
+
+/// Swaps `remove` for `add`, keeping the other options in order and dropping
+/// duplicates and empty entries left by stray commas.
 fn replace_option(options: &str, remove: &str, add: &str) -> String {
-    let mut opts: Vec<String> = options.split(',')
-    .filter(|&opt| opt != remove)
-    .map(|s| s.to_string())
-    .collect();
-    opts.push(add.to_string());
+    let mut opts: Vec<&str> = Vec::new();
+    for opt in options.split(',') {
+        if !opt.is_empty() && opt != remove && opt != add && !opts.contains(&opt) {
+            opts.push(opt);
+        }
+    }
+    opts.push(add);
     opts.join(",")
 }
 
@@ -272,3 +665,127 @@ fn reconstruct_fstab_line(parts: &[&str], new_opts: &str) -> String {
     if parts.len() > 5 { line.push_str(&format!("\t{}", parts[5])); }
     line
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 0:21 /@ / rw,noatime shared:1 - btrfs /dev/sda2 rw,ssd,subvol=/@
+30 22 0:21 /@/usr /usr rw,nosuid,nodev,relatime shared:1 - btrfs /dev/sda2 rw,ssd,subvol=/@
+31 22 8:1 / /boot rw,relatime shared:2 - vfat /dev/sda1 rw,fmask=0022
+";
+
+    #[test]
+    fn mount_flags_survive_lock_and_unlock() {
+        let flags = parse_mount_flags(MOUNTINFO, "/usr");
+        assert_eq!(flags, ["nosuid", "nodev", "relatime"]);
+        assert_eq!(remount_options(&flags, true), "remount,bind,ro,nosuid,nodev,relatime");
+
+        // What the kernel reports once the lock went through
+        let locked = MOUNTINFO.replace("/usr rw,nosuid", "/usr ro,nosuid");
+        let flags = parse_mount_flags(&locked, "/usr");
+        assert_eq!(flags, ["nosuid", "nodev", "relatime"]);
+        assert_eq!(remount_options(&flags, false), "remount,bind,rw,nosuid,nodev,relatime");
+    }
+
+    #[test]
+    fn mount_flags_of_unknown_path_are_empty() {
+        assert!(parse_mount_flags(MOUNTINFO, "/opt").is_empty());
+        assert_eq!(remount_options(&[], true), "remount,bind,ro");
+    }
+
+    #[test]
+    fn mountinfo_escapes_are_decoded() {
+        assert_eq!(unescape_mountinfo("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape_mountinfo("/mnt/a\\011b"), "/mnt/a\tb");
+        assert_eq!(unescape_mountinfo("/mnt/back\\134slash"), "/mnt/back\\slash");
+        assert_eq!(unescape_mountinfo("/mnt/caf\\303\\251"), "/mnt/café");
+        // Not an escape: too few or non-octal digits
+        assert_eq!(unescape_mountinfo("/mnt/x\\09"), "/mnt/x\\09");
+    }
+
+    #[test]
+    fn visible_mount_is_the_topmost_one() {
+        let stacked = format!(
+            "{}40 30 0:45 / /usr rw,relatime shared:20 - overlay overlay rw,lowerdir=/usr\n",
+            MOUNTINFO
+        );
+        assert_eq!(visible_mount(&stacked, "/usr"), Some(("rw,relatime".to_string(), "overlay".to_string())));
+        assert_eq!(
+            visible_mount(MOUNTINFO, "/usr"),
+            Some(("rw,nosuid,nodev,relatime".to_string(), "btrfs".to_string()))
+        );
+
+        let spaced = "41 22 0:50 / /mnt/my\\040disk ro,relatime - ext4 /dev/sdb1 ro\n";
+        assert_eq!(visible_mount(spaced, "/mnt/my disk"), Some(("ro,relatime".to_string(), "ext4".to_string())));
+        assert_eq!(visible_mount(spaced, "/mnt/my"), None);
+    }
+
+    #[test]
+    fn fstab_fields_skip_comments_and_split_tabs() {
+        assert_eq!(fstab_fields("# /etc/fstab: static file system information."), None);
+        assert_eq!(fstab_fields("   # indented comment"), None);
+        assert_eq!(fstab_fields("  "), None);
+        assert_eq!(
+            fstab_fields("UUID=ab12\t/boot  vfat\tumask=0077 0\t1"),
+            Some(vec!["UUID=ab12", "/boot", "vfat", "umask=0077", "0", "1"])
+        );
+    }
+
+    #[test]
+    fn replace_option_cleans_up_options() {
+        assert_eq!(replace_option("defaults", "rw", "ro"), "defaults,ro");
+        assert_eq!(replace_option("rw,,noatime,", "rw", "ro"), "noatime,ro");
+        assert_eq!(replace_option("ro,noatime,ro,noatime", "ro", "rw"), "noatime,rw");
+        assert_eq!(replace_option("noatime,ro", "rw", "ro"), "noatime,ro");
+    }
+
+    #[test]
+    fn reconstructed_lines_keep_present_fields_only() {
+        let full = fstab_fields("UUID=ab12 /boot vfat defaults 0 2").unwrap();
+        assert_eq!(reconstruct_fstab_line(&full, "defaults,ro"), "UUID=ab12\t/boot\tvfat\tdefaults,ro\t0\t2");
+
+        // dump and pass are optional and default to 0
+        let short = fstab_fields("UUID=ab12 /boot vfat defaults").unwrap();
+        assert_eq!(reconstruct_fstab_line(&short, "defaults,ro"), "UUID=ab12\t/boot\tvfat\tdefaults,ro");
+        let no_pass = fstab_fields("UUID=ab12 /boot vfat defaults 0").unwrap();
+        assert_eq!(reconstruct_fstab_line(&no_pass, "defaults,ro"), "UUID=ab12\t/boot\tvfat\tdefaults,ro\t0");
+    }
+
+    #[test]
+    fn opaque_dirs_are_read_from_getfattr() {
+        let output = "\
+# file: /run/hammer-overlay/upper/share/doc/foo
+trusted.overlay.opaque=\"y\"
+
+# file: /run/hammer-overlay/upper/lib/my\\040app
+trusted.overlay.opaque=\"y\"
+
+# file: /run/hammer-overlay/upper/share
+trusted.overlay.opaque=\"x\"
+";
+        let dirs = opaque_dirs(output, Path::new("/run/hammer-overlay/upper"));
+        assert_eq!(dirs, [PathBuf::from("share/doc/foo"), PathBuf::from("lib/my app")]);
+    }
+
+    #[test]
+    fn replaced_dirs_lose_their_lower_contents() {
+        let target = std::env::temp_dir().join(format!("hammer-read-replace-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&target);
+        fs::create_dir_all(target.join("share/foo/nested")).unwrap();
+        fs::write(target.join("share/foo/old"), "lower").unwrap();
+        fs::write(target.join("share/keep"), "lower").unwrap();
+
+        let changes = [
+            OverlayChange::Replace("share/foo".into()),
+            OverlayChange::Replace("share/foo/nested".into()),
+            OverlayChange::Write("share/foo/new".into()),
+        ];
+        clear_replaced_dirs(&target, &changes).unwrap();
+
+        assert!(!target.join("share/foo").exists());
+        assert!(target.join("share/keep").exists());
+        let _ = fs::remove_dir_all(target);
+    }
+}
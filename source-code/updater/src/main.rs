@@ -1,47 +1,555 @@
 use miette::{IntoDiagnostic, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use hammer_core::{
+    apt_source_entries, boot_is_separate, boot_sanity_problems, btrfs_capture_boot,
     btrfs_delete_atomic_snapshot, btrfs_list_atomic_snapshots, btrfs_snapshot_atomic,
-    create_spinner, create_progress_bar, run_command, Logger,
+    btrfs_snapshot_tree, btrfs_update_snapshot_meta, btrfs_vacuum_snapshot_logs,
+    calculate_dir_size, check_free_space, compute_system_version, copy_boot_images,
+    create_spinner, create_progress_bar, format_age, format_size, installed_packages,
+    mount_btrfs_root, parse_subvolume_list, run_command, run_command_status, system_suite,
+    umount_btrfs_root, wait_or_kill, HammerError, LogLevel, Logger, Paths, Snapshot, SnapshotMeta, SnapshotStore,
+    APT_SOURCES_DIR, APT_SOURCES_LIST, NETWORK_RETRIES, NETWORK_RETRY_DELAY,
+    SNAPSHOT_NAME_FORMAT,
 };
-use owo_colors::OwoColorize;
+use owo_colors::{OwoColorize, Stream};
 use dialoguer::{Select, Confirm};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
 use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use indicatif::ProgressBar;
 
+/// Debian releases in upgrade order. `sid` always sorts last.
+const DEBIAN_SUITES: &[&str] = &["buster", "bullseye", "bookworm", "trixie", "forky", "duke", "sid"];
+/// grub.d script holding the generated "Hammer snapshots" submenu
+const GRUB_SNAPSHOT_SCRIPT: &str = "/etc/grub.d/42_hammer_snapshots";
+
 #[derive(Parser)]
 #[command(name = "hammer-updater")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Take over the lock even if another hammer process holds it
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Log the snapshot, mount and apt commands instead of running them
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Only print errors
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print debug detail, such as every command run
+    #[arg(long, short, global = true)]
+    verbose: bool,
+
+    /// Print without colours, same as setting NO_COLOR
+    #[arg(long, global = true)]
+    no_color: bool,
+}
+
+#[derive(Args)]
+struct UpdateArgs {
+    /// Empty /var/log inside the pre-update snapshot
+    #[arg(long)]
+    vacuum_logs: bool,
+
+    /// Also install this package as part of the update (repeatable)
+    #[arg(long = "include", value_name = "PKG")]
+    include: Vec<String>,
+
+    /// Hold this package back for this update only (repeatable)
+    #[arg(long = "exclude", value_name = "PKG")]
+    exclude: Vec<String>,
+
+    /// Update even if an APT source disables signature checks
+    #[arg(long)]
+    allow_insecure_repos: bool,
+
+    /// Skip the free space check before snapshotting
+    #[arg(long)]
+    skip_space_check: bool,
+
+    /// Free space to require on top of the size of @, in MB
+    #[arg(long, value_name = "MB", default_value_t = 2048)]
+    space_headroom: u64,
+
+    /// Kill the package upgrade if it runs longer than this many seconds
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Update,
+    Update(UpdateArgs),
     Layer { packages: Vec<String> },
-    Clean,
-    Rollback,
+    Clean {
+        /// Clean hammer's own logs instead of snapshots
+        #[arg(long)]
+        logs: bool,
+
+        /// With --logs, keep rotated logs younger than this many days
+        #[arg(long, default_value_t = 14)]
+        keep_days: u64,
+
+        /// Number of newest snapshots to keep
+        #[arg(long, default_value_t = 3)]
+        keep: usize,
+    },
+    Rollback {
+        /// Step back this many snapshots (1 = newest) instead of choosing from a list
+        steps: Option<usize>,
+
+        /// Don't ask for confirmation; required when stdin is not a terminal
+        #[arg(long, short)]
+        yes: bool,
+
+        /// Switch even if the snapshot's kernel does not line up with /boot
+        #[arg(long)]
+        confirm_reboot_safe: bool,
+    },
+    /// Show the running system's kernel, package set and update state
+    Status {
+        /// Print the status as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Take a manual snapshot of the live system
+    Snapshot {
+        /// Label appended to the timestamp, e.g. before-driver-swap
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Note stored in the snapshot's metadata and shown by list
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Show the snapshot timeline, oldest first, ending at the live system
+    History,
+    /// Check the preconditions for updates and rollbacks
+    Doctor,
+    /// Re-check a snapshot's package set and kernel against its metadata
+    Verify {
+        /// Snapshot to check, defaults to the newest
+        snapshot: Option<String>,
+    },
+    /// Keep a snapshot from being deleted by clean and prune
+    Pin { snapshot: String },
+    /// Let clean and prune delete a pinned snapshot again
+    Unpin { snapshot: String },
+    /// List snapshots and their metadata
+    List {
+        /// Print the snapshot metadata as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Upgrade to another Debian release behind a restore-point snapshot
+    SetSuite {
+        /// Target release codename, e.g. trixie
+        suite: String,
+
+        /// Allow moving to an older release
+        #[arg(long = "i-know-what-im-doing")]
+        allow_downgrade: bool,
+    },
+    /// Compare the APT sources of a snapshot with another snapshot or the running system
+    DiffConfig {
+        /// Snapshot to compare from
+        a: String,
+        /// Snapshot to compare to (default: the running system)
+        b: Option<String>,
+    },
+    /// Show which packages changed between a snapshot and another snapshot or the running system
+    Diff {
+        /// Snapshot to compare from (default: the newest snapshot)
+        a: Option<String>,
+        /// Snapshot to compare to (default: the running system)
+        b: Option<String>,
+    },
+    /// Delete snapshots older than a given age
+    Prune {
+        /// Maximum age to keep, e.g. 30d, 12h, 2w
+        #[arg(long, value_name = "DURATION")]
+        older_than: String,
+
+        /// Always keep at least this many of the newest snapshots
+        #[arg(long, default_value_t = 1)]
+        keep_min: usize,
+    },
+    /// Empty /var/log inside existing snapshots (the running system is never touched)
+    VacuumLogs {
+        /// Only vacuum this snapshot instead of all of them
+        snapshot: Option<String>,
+    },
+    /// Copy the separate /boot partition's kernels into existing snapshots
+    SnapshotBoot {
+        /// Only copy into this snapshot instead of all of them
+        snapshot: Option<String>,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    if cli.quiet {
+        Logger::set_level(LogLevel::Error);
+    } else if cli.verbose {
+        Logger::set_level(LogLevel::Debug);
+    }
+    if cli.no_color {
+        Logger::set_color(false);
+    }
+    // status and doctor only read, and report whether someone else holds the lock
+    let _lock = match cli.command {
+        Commands::Status { .. } | Commands::Doctor => None,
+        _ => Some(acquire_lock(&Paths::get().lock_file, cli.force)?),
+    };
+
+    if cli.dry_run {
+        let supported = matches!(
+            cli.command,
+            Commands::Update(_)
+            | Commands::Layer { .. }
+            | Commands::Clean { logs: false, .. }
+            | Commands::Prune { .. }
+            | Commands::Snapshot { .. }
+        );
+        if !supported {
+            return Err(HammerError::ConfigError("--dry-run is only supported by update, layer, clean, prune and snapshot".into()).into());
+        }
+        Logger::warn("DRY RUN: commands are logged, nothing is executed.");
+    }
+
     match cli.command {
-        Commands::Update => handle_update()?,
-        Commands::Layer { packages } => handle_layer(packages)?,
-        Commands::Clean => handle_clean()?,
-        Commands::Rollback => handle_rollback()?,
+        Commands::Update(args) => handle_update(&args, cli.dry_run)?,
+        Commands::Layer { packages } => handle_layer(packages, cli.dry_run)?,
+        Commands::Clean { logs: true, keep_days, .. } => handle_clean_logs(keep_days)?,
+        Commands::Clean { logs: false, keep, .. } => handle_clean(keep, cli.dry_run)?,
+        Commands::Rollback { steps, yes, confirm_reboot_safe } => handle_rollback(steps, yes, confirm_reboot_safe)?,
+        Commands::Status { json } => handle_status(json)?,
+        Commands::Snapshot { name, description } => handle_manual_snapshot(name, description, cli.dry_run)?,
+        Commands::History => handle_history()?,
+        Commands::Doctor => handle_doctor()?,
+        Commands::Verify { snapshot } => handle_verify(snapshot)?,
+        Commands::Pin { snapshot } => handle_pin(&snapshot, true)?,
+        Commands::Unpin { snapshot } => handle_pin(&snapshot, false)?,
+        Commands::List { json } => handle_list(json)?,
+        Commands::SetSuite { suite, allow_downgrade } => handle_set_suite(suite, allow_downgrade)?,
+        Commands::DiffConfig { a, b } => handle_diff_config(&a, b.as_deref())?,
+        Commands::Diff { a, b } => handle_diff(a, b.as_deref())?,
+        Commands::Prune { older_than, keep_min } => handle_prune(&older_than, keep_min, cli.dry_run)?,
+        Commands::VacuumLogs { snapshot } => handle_vacuum_logs(snapshot)?,
+        Commands::SnapshotBoot { snapshot } => handle_snapshot_boot(snapshot)?,
     }
     Ok(())
 }
 
+/// Removes the lock file when the updater exits, including on error paths.
+struct LockGuard {
+    path: &'static Path,
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        // A --force run may have taken the lock over in the meantime, leave theirs alone
+        if read_lock_owner(self.path) == Some(std::process::id()) {
+            let _ = fs::remove_file(self.path);
+        }
+    }
+}
+
+/// Takes the lock at `path`. The PID is written to a private file first and
+/// linked into place, so the lock never exists without an owner in it.
+fn acquire_lock(path: &'static Path, force: bool) -> Result<LockGuard> {
+    let pid = std::process::id();
+    let tmp = std::path::PathBuf::from(format!("{}.{}", path.display(), pid));
+    fs::write(&tmp, pid.to_string()).into_diagnostic()?;
+    let taken = take_lock(path, &tmp, force);
+    let _ = fs::remove_file(&tmp);
+    taken.map(|_| LockGuard { path })
+}
+
+fn take_lock(path: &Path, tmp: &Path, force: bool) -> Result<()> {
+    match fs::hard_link(tmp, path) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).into_diagnostic(),
+    }
+
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    match lock_owner(&contents, process_alive) {
+        LockOwner::Running(pid) if !force => {
+            return Err(HammerError::LockError(format!(
+                "{} is held by running process {}", path.display(), pid
+            )).into());
+        }
+        LockOwner::Unknown if !force => {
+            return Err(HammerError::LockError(format!(
+                "{} exists but holds no PID; pass --force if no other hammer is running", path.display()
+            )).into());
+        }
+        LockOwner::Running(pid) => {
+            Logger::warn(&format!("Forcibly taking over lock held by process {}", pid));
+        }
+        LockOwner::Unknown => {
+            Logger::warn(&format!("Forcibly taking over lock {}", path.display()));
+        }
+        LockOwner::Stale(pid) => {
+            Logger::warn(&format!("Removing stale lock {} (process {} no longer running)", path.display(), pid));
+        }
+    }
+    // Replaces the old lock in one step, there is no moment without a lock file
+    fs::rename(tmp, path).into_diagnostic()
+}
+
+/// Who holds a lock file, going by the PID written into it.
+#[derive(Debug, PartialEq, Eq)]
+enum LockOwner {
+    Running(u32),
+    /// The PID belongs to no running process
+    Stale(u32),
+    /// No PID could be read, so the lock is treated as held
+    Unknown,
+}
+
+/// Classifies the contents of a lock file; `alive` says whether a PID runs.
+fn lock_owner(contents: &str, alive: impl Fn(u32) -> bool) -> LockOwner {
+    match contents.trim().parse() {
+        Ok(pid) if alive(pid) => LockOwner::Running(pid),
+        Ok(pid) => LockOwner::Stale(pid),
+        Err(_) => LockOwner::Unknown,
+    }
+}
+
+fn read_lock_owner(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Runs a command, or only logs it when `dry_run` is set.
+fn exec(dry_run: bool, cmd: &str, args: &[&str], description: &str) -> Result<String> {
+    if dry_run {
+        Logger::info(&format!("[dry-run] {} {}", cmd, args.join(" ")));
+        return Ok(String::new());
+    }
+    run_command(cmd, args, description)
+}
+
+/// Runs `apt update`, retried with back-off like `run_command_retry`, then fails if apt reported
+/// a repository whose signature is missing or does not check out. apt only
+/// warns about some of these and exits 0, so its stderr is checked either way.
+fn apt_update(dry_run: bool) -> Result<()> {
+    if dry_run {
+        Logger::info("[dry-run] apt update");
+        return Ok(());
+    }
+    let mut delay = NETWORK_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        let output = run_command_status("apt", &["update"])?;
+        let problems = apt_signature_problems(&output.stderr);
+        if !problems.is_empty() {
+            for line in &problems {
+                Logger::error(line);
+            }
+            return Err(HammerError::ConfigError(
+                "APT could not verify repository signatures. Fix the keys or sources before updating.".into()
+            ).into());
+        }
+        if output.success() {
+            return Ok(());
+        }
+        Logger::log(&format!("Command failed stderr: {}", output.stderr));
+        if attempt >= NETWORK_RETRIES {
+            return Err(HammerError::CommandFailed(format!("APT Update failed: {}", output.stderr)).into());
+        }
+        Logger::warn(&format!(
+            "APT Update failed (attempt {}/{}), retrying in {}s...",
+            attempt, NETWORK_RETRIES, delay.as_secs()
+        ));
+        std::thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Lines of `apt update` stderr that report a missing, expired or bad signature.
+fn apt_signature_problems(stderr: &str) -> Vec<&str> {
+    stderr
+    .lines()
+    .map(str::trim)
+    .filter(|line| {
+        ["NO_PUBKEY", "EXPKEYSIG", "is not signed", "signatures were invalid"]
+        .iter()
+        .any(|marker| line.contains(marker))
+    })
+    .collect()
+}
+
+/// Like `exec`, but the command shares our terminal. Returns whether it succeeded.
+fn exec_streamed(dry_run: bool, cmd: &str, args: &[&str]) -> Result<bool> {
+    exec_streamed_timeout(dry_run, cmd, args, None)
+}
+
+/// `exec_streamed` that kills the command once `timeout` passes. With a timeout
+/// the command runs in its own process group without stdin, since a background
+/// group reading the terminal would be stopped, and debconf is told not to ask.
+fn exec_streamed_timeout(dry_run: bool, cmd: &str, args: &[&str], timeout: Option<Duration>) -> Result<bool> {
+    if dry_run {
+        Logger::info(&format!("[dry-run] {} {}", cmd, args.join(" ")));
+        return Ok(true);
+    }
+    // Same trace line as run_command, the output itself goes to the terminal
+    Logger::debug(&format!("Running: {} {}", cmd, args.join(" ")));
+    let Some(timeout) = timeout else {
+        let status = Command::new(cmd)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .into_diagnostic()?;
+        return Ok(status.success());
+    };
+
+    let mut child = Command::new(cmd)
+    .args(args)
+    .stdin(Stdio::null())
+    .stdout(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .env("DEBIAN_FRONTEND", "noninteractive")
+    .process_group(0)
+    .spawn()
+    .into_diagnostic()?;
+    match wait_or_kill(&mut child, timeout)? {
+        Some(status) => Ok(status.success()),
+        None => Err(HammerError::CommandFailed(format!(
+            "{} {} timed out after {}s", cmd, args.join(" "), timeout.as_secs()
+        )).into()),
+    }
+}
+
+fn snapshot(dry_run: bool, name: &str, kind: &str) -> Result<()> {
+    if dry_run {
+        let paths = Paths::get();
+        Logger::info(&format!(
+            "[dry-run] btrfs subvolume snapshot {} {}",
+            paths.root_subvol_path().display(),
+            paths.snapshots_dir().join(name).display()
+        ));
+        return Ok(());
+    }
+    btrfs_snapshot_atomic(name, kind)
+}
+
+/// Verifies the live root can still boot after apt changed it. On failure the
+/// problems are listed and the caller is pointed at the restore point.
+fn ensure_bootable(restore_point: &str) -> Result<()> {
+    let problems = boot_sanity_problems(Path::new("/"));
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for problem in &problems {
+        Logger::error(problem);
+    }
+    Logger::warn(&format!("Do NOT reboot. Roll back with 'hammer rollback' and pick {}.", restore_point));
+    Err(HammerError::CommandFailed("Post-upgrade sanity check failed".into()).into())
+}
+
+/// Requires room for a full copy of @ plus `headroom` bytes on the Btrfs
+/// filesystem, so an upgrade that rewrites most of the system cannot fill it.
+fn ensure_free_space(headroom: u64) -> Result<()> {
+    let mount_point = mount_btrfs_root()?;
+    let result = calculate_dir_size(&Paths::get().root_subvol_path()).and_then(|root_size| {
+        Logger::info(&format!("Current root uses {}", format_size(root_size)));
+        check_free_space(Path::new(&mount_point), root_size + headroom)
+    });
+    umount_btrfs_root()?;
+    result
+}
+
 fn create_snapshot_name(suffix: &str) -> String {
-    let timestamp = chrono::Local::now().format("%Y-%m-%d-%H%M%S");
+    let timestamp = chrono::Local::now().format(SNAPSHOT_NAME_FORMAT);
     format!("{}-{}", timestamp, suffix)
 }
 
-fn handle_update() -> Result<()> {
+/// What `handle_update` had got done when it stopped, printed if it fails.
+struct UpdateAftermath {
+    phase: &'static str,
+    restore_point: Option<String>,
+    held: Vec<String>,
+}
+
+impl UpdateAftermath {
+    fn report(&self) {
+        Logger::end_section();
+        Logger::section("UPDATE AFTERMATH");
+        Logger::error(&format!("Update failed during: {}", self.phase));
+
+        match &self.restore_point {
+            Some(name) => Logger::info(&format!("Restore point {} was kept in @snapshots.", name.if_supports_color(Stream::Stdout, |t| t.cyan()))),
+            None => Logger::info("No restore point was taken, packages were not touched."),
+        }
+        if !self.held.is_empty() {
+            Logger::warn(&format!(
+                "Still held: {}. Release with 'apt-mark unhold {}'.",
+                self.held.join(", "), self.held.join(" ")
+            ));
+        }
+        Logger::info("The hammer lock is released when this process exits.");
+
+        if self.restore_point.is_some() {
+            Logger::info("Next: fix the error above and re-run 'hammer update', or revert with 'hammer rollback'.");
+        } else {
+            Logger::info("Next: fix the error above and re-run 'hammer update'.");
+        }
+        Logger::end_section();
+    }
+}
+
+fn handle_update(args: &UpdateArgs, dry_run: bool) -> Result<()> {
     Logger::section("ATOMIC SYSTEM UPDATE");
+    let mut aftermath = UpdateAftermath { phase: "preflight", restore_point: None, held: Vec::new() };
+    let result = run_update(args, dry_run, &mut aftermath);
+    if result.is_err() {
+        aftermath.report();
+    }
+    result
+}
+
+fn run_update(args: &UpdateArgs, dry_run: bool, aftermath: &mut UpdateAftermath) -> Result<()> {
+    let started = Instant::now();
+    let (include, exclude) = (&args.include, &args.exclude);
+
+    let insecure = insecure_apt_sources(Path::new("/"));
+    if !insecure.is_empty() {
+        for entry in &insecure {
+            Logger::warn(&format!("Unverified source: {}", entry));
+        }
+        if !args.allow_insecure_repos {
+            return Err(HammerError::ConfigError(
+                "APT sources disable signature verification. Fix them or pass --allow-insecure-repos.".into()
+            ).into());
+        }
+    }
+
+    if args.skip_space_check {
+        Logger::warn("Skipping free space check.");
+    } else {
+        aftermath.phase = "checking free space";
+        ensure_free_space(args.space_headroom * 1024 * 1024)?;
+    }
 
     // Initialize global progress bar for steps
     let steps = 4;
@@ -52,96 +560,208 @@ fn handle_update() -> Result<()> {
     main_pb.set_position(1);
 
     // Ensure RW
+    aftermath.phase = "remounting / read-write";
     Logger::info("Remounting Root as RW...");
-    run_command("mount", &["-o", "remount,rw", "/"], "Remount RW")?;
+    exec(dry_run, "mount", &["-o", "remount,rw", "/"], "Remount RW")?;
 
     // Step 2: Snapshot
     main_pb.set_message("Step 2/4: Creating Snapshot...");
     main_pb.set_position(2);
 
     let snap_name = create_snapshot_name("pre-update");
+    aftermath.phase = "creating the restore point";
     let spinner = create_spinner("Snapshotting @ subvolume...");
-    btrfs_snapshot_atomic(&snap_name)?;
+    snapshot(dry_run, &snap_name, "pre-update")?;
+    aftermath.restore_point = Some(snap_name.clone());
     spinner.finish_with_message("Snapshot created in @snapshots");
 
+    if args.vacuum_logs && !dry_run {
+        aftermath.phase = "vacuuming snapshot logs";
+        let reclaimed = btrfs_vacuum_snapshot_logs(&snap_name)?;
+        Logger::info(&format!("Vacuumed snapshot logs, reclaimed {}", format_size(reclaimed)));
+    }
+
     // Step 3: APT Update
     main_pb.set_message("Step 3/4: Downloading Updates...");
     main_pb.set_position(3);
 
+    aftermath.phase = "apt update";
+    let packages_before = installed_packages(Path::new("/"))?;
+    let kernels_before = installed_kernels();
+
     Logger::info("Running apt update & upgrade (Logs below)...");
 
     // We pause the main PB briefly or let logs flow under it?
     // indicatif output handles this if configured, but mixing streams is hard.
     // We will just let logs print.
 
-    let status = Command::new("apt")
-    .args(&["update"])
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .into_diagnostic()?;
+    apt_update(dry_run)?;
 
-    if !status.success() {
-        Logger::error("apt update failed.");
-        return Ok(());
+    if !include.is_empty() {
+        Logger::info(&format!("Including: {}", include.join(", ").if_supports_color(Stream::Stdout, |t| t.cyan())));
+    }
+    if !exclude.is_empty() {
+        Logger::info(&format!("Excluding: {}", exclude.join(", ").if_supports_color(Stream::Stdout, |t| t.cyan())));
     }
 
-    let status = Command::new("apt")
-    .args(&["full-upgrade", "-y"])
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .into_diagnostic()?;
+    // Only unhold what we held ourselves, packages the admin already held stay held
+    let already_held = run_command("apt-mark", &["showhold"], "List Held Packages")?;
+    let to_hold: Vec<&str> = exclude
+    .iter()
+    .map(|p| p.as_str())
+    .filter(|p| !already_held.lines().any(|held| held.trim() == *p))
+    .collect();
+    if !to_hold.is_empty() {
+        let mut cmd_args = vec!["hold"];
+        cmd_args.extend(&to_hold);
+        aftermath.phase = "holding excluded packages";
+        exec(dry_run, "apt-mark", &cmd_args, "Hold Excluded Packages")?;
+        aftermath.held = to_hold.iter().map(|p| p.to_string()).collect();
+    }
+
+    aftermath.phase = "apt full-upgrade";
+    let timeout = args.timeout.map(Duration::from_secs);
+    // Without stdin a conffile prompt would just sit there until the timeout,
+    // so keep local config files and take the defaults instead
+    let unattended: &[&str] = if timeout.is_some() {
+        &["-o", "Dpkg::Options::=--force-confdef", "-o", "Dpkg::Options::=--force-confold"]
+    } else {
+        &[]
+    };
+    let mut cmd_args = vec!["full-upgrade", "-y"];
+    cmd_args.extend(unattended);
+    // A failure or timeout is only returned once the holds above are released again
+    let mut upgraded = exec_streamed_timeout(dry_run, "apt", &cmd_args, timeout);
+    if matches!(upgraded, Ok(true)) && !include.is_empty() {
+        let mut cmd_args = vec!["install", "-y"];
+        cmd_args.extend(unattended);
+        cmd_args.extend(include.iter().map(|p| p.as_str()));
+        aftermath.phase = "installing included packages";
+        upgraded = exec_streamed_timeout(dry_run, "apt", &cmd_args, timeout);
+    }
 
-    if status.success() {
+    if !to_hold.is_empty() {
+        let mut cmd_args = vec!["unhold"];
+        cmd_args.extend(&to_hold);
+        exec(dry_run, "apt-mark", &cmd_args, "Release Excluded Packages")?;
+        aftermath.held.clear();
+    }
+    let upgraded = upgraded?;
+
+    if upgraded {
         // Step 4: Finalize
         main_pb.set_message("Step 4/4: Finalizing...");
         main_pb.set_position(4);
 
-        run_command("sync", &[], "Sync Filesystem")?;
+        aftermath.phase = "post-update checks";
+        if !dry_run {
+            ensure_bootable(&snap_name)?;
+        }
+        exec(dry_run, "sync", &[], "Sync Filesystem")?;
+
+        if dry_run {
+            main_pb.finish_with_message("Dry run complete.");
+            Logger::end_section();
+            return Ok(());
+        }
 
         main_pb.finish_with_message("Update Complete!");
         Logger::success("System successfully updated.");
+        update_bootloader_entries(dry_run);
+
+        let changes = PackageChanges::between(&packages_before, &installed_packages(Path::new("/"))?);
+        let new_kernels: Vec<String> = installed_kernels()
+        .into_iter()
+        .filter(|k| !kernels_before.contains(k))
+        .collect();
+        print_update_summary(&snap_name, &changes, &new_kernels, started);
     } else {
         main_pb.abandon_with_message("Update Failed");
-        Logger::error("APT Upgrade failed.");
-
-        if Confirm::new().with_prompt("Rollback now?").interact().into_diagnostic()? {
-            // Rollback logic here (complex on live system)
-            Logger::warn("Please run 'hammer rollback' or select snapshot at boot.");
-        }
+        return Err(HammerError::CommandFailed("APT upgrade failed".into()).into());
     }
 
     Logger::end_section();
     Ok(())
 }
 
-fn handle_layer(packages: Vec<String>) -> Result<()> {
+/// Package differences between two `name -> version` maps.
+#[derive(Default)]
+struct PackageChanges {
+    added: Vec<(String, String)>,
+    removed: Vec<(String, String)>,
+    changed: Vec<(String, String, String)>,
+}
+
+impl PackageChanges {
+    fn between(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Self {
+        let mut changes = Self::default();
+        for (name, version) in new {
+            match old.get(name) {
+                None => changes.added.push((name.clone(), version.clone())),
+                Some(old_version) if old_version != version => {
+                    changes.changed.push((name.clone(), old_version.clone(), version.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, version) in old {
+            if !new.contains_key(name) {
+                changes.removed.push((name.clone(), version.clone()));
+            }
+        }
+        changes
+    }
+}
+
+fn installed_kernels() -> Vec<String> {
+    fs::read_dir("/boot")
+    .map(|entries| {
+        entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_string_lossy().strip_prefix("vmlinuz-").map(str::to_string))
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn print_update_summary(snap_name: &str, changes: &PackageChanges, new_kernels: &[String], started: Instant) {
+    Logger::info(&format!("Restore point: {}", snap_name.if_supports_color(Stream::Stdout, |t| t.cyan())));
+    Logger::info(&format!(
+        "Packages: {} upgraded, {} added, {} removed",
+        changes.changed.len(),
+        changes.added.len(),
+        changes.removed.len()
+    ));
+    if new_kernels.is_empty() {
+        Logger::info("Kernel: unchanged");
+    } else {
+        Logger::info(&format!("New kernel: {} (reboot to use it)", new_kernels.join(", ").if_supports_color(Stream::Stdout, |t| t.green())));
+    }
+    Logger::info(&format!("Duration: {:.0?}", started.elapsed()));
+}
+
+fn handle_layer(packages: Vec<String>, dry_run: bool) -> Result<()> {
     if packages.is_empty() { return Ok(()); }
 
     Logger::section("PACKAGE LAYERING");
-    run_command("mount", &["-o", "remount,rw", "/"], "Remount RW")?;
+    exec(dry_run, "mount", &["-o", "remount,rw", "/"], "Remount RW")?;
 
     let snap_name = create_snapshot_name("pre-layer");
     let spinner = create_spinner("Safety Snapshot...");
-    btrfs_snapshot_atomic(&snap_name)?;
+    snapshot(dry_run, &snap_name, "pre-layer")?;
     spinner.finish_with_message("Snapshot created.");
 
     let mut args = vec!["install", "-y"];
     let pkgs_refs: Vec<&str> = packages.iter().map(|s| s.as_str()).collect();
     args.extend(pkgs_refs);
 
-    let status = Command::new("apt")
-    .args(&args)
-    .stdin(Stdio::inherit())
-    .stdout(Stdio::inherit())
-    .stderr(Stdio::inherit())
-    .status()
-    .into_diagnostic()?;
-
-    if status.success() {
-        run_command("sync", &[], "Sync")?;
+    if exec_streamed(dry_run, "apt", &args)? {
+        if !dry_run {
+            ensure_bootable(&snap_name)?;
+        }
+        exec(dry_run, "sync", &[], "Sync")?;
         Logger::success("Layer applied.");
+        update_bootloader_entries(dry_run);
     } else {
         Logger::error("Failed.");
     }
@@ -149,72 +769,1231 @@ fn handle_layer(packages: Vec<String>) -> Result<()> {
     Ok(())
 }
 
-fn handle_clean() -> Result<()> {
+fn handle_set_suite(target: String, allow_downgrade: bool) -> Result<()> {
+    Logger::section("RELEASE UPGRADE");
+
+    let current = system_suite(Path::new("/")).ok_or_else(|| {
+        HammerError::ConfigError("Could not read VERSION_CODENAME from /etc/os-release".into())
+    })?;
+    validate_suite_transition(&current, &target, allow_downgrade)?;
+
+    // Same preflight as update, before anything is snapshotted or rewritten
+    let insecure = insecure_apt_sources(Path::new("/"));
+    if !insecure.is_empty() {
+        for entry in &insecure {
+            Logger::warn(&format!("Unverified source: {}", entry));
+        }
+        return Err(HammerError::ConfigError(
+            "APT sources disable signature verification. Fix them before changing suites.".into()
+        ).into());
+    }
+    // update's default --space-headroom
+    ensure_free_space(2048 * 1024 * 1024)?;
+
+    Logger::info(&format!(
+        "Switching suite {} -> {}",
+        current.if_supports_color(Stream::Stdout, |t| t.yellow()),
+        target.if_supports_color(Stream::Stdout, |t| t.green())
+    ));
+    run_command("mount", &["-o", "remount,rw", "/"], "Remount RW")?;
+
+    let snap_name = create_snapshot_name("pre-set-suite");
+    let spinner = create_spinner("Snapshotting @ subvolume...");
+    btrfs_snapshot_atomic(&snap_name, "pre-set-suite")?;
+    btrfs_update_snapshot_meta(&snap_name, |meta| meta.target_suite = Some(target.clone()))?;
+    spinner.finish_with_message("Snapshot created in @snapshots");
+
+    let originals = rewrite_apt_sources(&current, &target)?;
+    if originals.is_empty() {
+        return Err(HammerError::ConfigError(format!(
+            "No APT source references '{}'. Sources using aliases like 'stable' must be edited by hand.",
+            current
+        )).into());
+    }
+    Logger::info(&format!("Rewrote {} APT source file(s).", originals.len()));
+
+    let upgraded = apt_update(false)
+    .and_then(|_| exec_streamed(false, "apt", &["full-upgrade", "-y"]))
+    .unwrap_or_else(|err| {
+        Logger::error(&err.to_string());
+        false
+    });
+    if !upgraded {
+        restore_apt_sources(&originals);
+        Logger::error("Upgrading to the new release failed.");
+        Logger::warn(&format!("Restore the previous release with 'hammer rollback' and pick {}.", snap_name));
+        return Err(HammerError::CommandFailed(format!("apt upgrade to {}", target)).into());
+    }
+
+    ensure_bootable(&snap_name)?;
+    run_command("sync", &[], "Sync Filesystem")?;
+    Logger::success(&format!("Upgraded to {}. Reboot to apply changes.", target));
+    update_bootloader_entries(false);
+    Logger::end_section();
+    Ok(())
+}
+
+fn validate_suite_transition(current: &str, target: &str, allow_downgrade: bool) -> Result<()> {
+    let rank = |suite: &str| DEBIAN_SUITES.iter().position(|s| *s == suite);
+
+    let (Some(from), Some(to)) = (rank(current), rank(target)) else {
+        return Err(HammerError::ConfigError(format!(
+            "Unsupported suite transition {} -> {} (known suites: {})",
+            current, target, DEBIAN_SUITES.join(", ")
+        )).into());
+    };
+
+    if from == to {
+        return Err(HammerError::ConfigError(format!("System is already on {}", current)).into());
+    }
+    if to < from && !allow_downgrade {
+        return Err(HammerError::ConfigError(format!(
+            "{} -> {} is a downgrade. Pass --i-know-what-im-doing to continue.", current, target
+        )).into());
+    }
+    // Debian only supports upgrading one release at a time; sid is reachable from anywhere
+    if to > from + 1 && target != "sid" {
+        return Err(HammerError::ConfigError(format!(
+            "Cannot skip releases: upgrade {} to {} first.", current, DEBIAN_SUITES[from + 1]
+        )).into());
+    }
+    Ok(())
+}
+
+/// Replaces `from` with `to` in every APT source file (keeping suffixes like
+/// `-security`). Returns the original contents of the files changed, for
+/// `restore_apt_sources`. If a write fails the files already changed are restored.
+fn rewrite_apt_sources(from: &str, to: &str) -> Result<Vec<(std::path::PathBuf, String)>> {
+    let pattern = Regex::new(&format!(r"\b{}\b", regex::escape(from))).into_diagnostic()?;
+
+    let mut files = vec![Path::new(APT_SOURCES_LIST).to_path_buf()];
+    if let Ok(entries) = fs::read_dir(APT_SOURCES_DIR) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if matches!(path.extension().and_then(|e| e.to_str()), Some("list") | Some("sources")) {
+                files.push(path);
+            }
+        }
+    }
+
+    let mut originals = Vec::new();
+    for file in files {
+        let Ok(content) = fs::read_to_string(&file) else { continue };
+        let updated = pattern.replace_all(&content, to);
+        if updated == content {
+            continue;
+        }
+        if let Err(err) = fs::write(&file, updated.as_ref()) {
+            restore_apt_sources(&originals);
+            return Err(err).into_diagnostic();
+        }
+        originals.push((file, content));
+    }
+    Ok(originals)
+}
+
+/// Writes back the files `rewrite_apt_sources` changed, so a failed suite change
+/// does not leave the live system pointed at the new release.
+fn restore_apt_sources(originals: &[(std::path::PathBuf, String)]) {
+    for (file, content) in originals {
+        if let Err(err) = fs::write(file, content) {
+            Logger::error(&format!("Could not restore {}: {}", file.display(), err));
+        }
+    }
+    if !originals.is_empty() {
+        Logger::info(&format!("Restored {} APT source file(s).", originals.len()));
+    }
+}
+
+/// A snapshot that carries a bootable kernel and initrd in its own /boot.
+struct SnapshotBootEntry {
+    name: String,
+    kernel: String,
+    title: String,
+    /// Subvolume path relative to the top-level root, e.g. `@snapshots/<name>`
+    subvol: String,
+    /// The snapshot's /boot as seen under the mounted top-level root
+    boot_dir: std::path::PathBuf,
+}
+
+/// Writes boot menu entries for snapshots. The btrfs top-level root is mounted
+/// while `write_entries` runs, so `boot_dir` paths are readable.
+trait Bootloader {
+    fn name(&self) -> &'static str;
+    /// Returns how many of `entries` the menu lists.
+    fn write_entries(&self, root_uuid: &str, entries: &[SnapshotBootEntry]) -> Result<usize>;
+}
+
+struct Grub;
+
+impl Bootloader for Grub {
+    fn name(&self) -> &'static str {
+        "GRUB"
+    }
+
+    fn write_entries(&self, root_uuid: &str, entries: &[SnapshotBootEntry]) -> Result<usize> {
+        if entries.is_empty() {
+            if Path::new(GRUB_SNAPSHOT_SCRIPT).exists() {
+                fs::remove_file(GRUB_SNAPSHOT_SCRIPT).into_diagnostic()?;
+            }
+        } else {
+            let menu: String = entries
+            .iter()
+            .map(|e| format!(
+                "    menuentry '{}' --class gnu-linux {{\n        insmod btrfs\n        search --no-floppy --fs-uuid --set=root {}\n        linux /{}/boot/vmlinuz-{} root=UUID={} rootflags=subvol={} ro\n        initrd /{}/boot/initrd.img-{}\n    }}\n",
+                e.title, root_uuid, e.subvol, e.kernel, root_uuid, e.subvol, e.subvol, e.kernel
+            ))
+            .collect();
+            let script = format!(
+                "#!/bin/sh\nexec tail -n +4 $0\n# Generated by hammer-updater, changes will be overwritten\nsubmenu 'Hammer snapshots' {{\n{}}}\n",
+                menu
+            );
+            fs::write(GRUB_SNAPSHOT_SCRIPT, script).into_diagnostic()?;
+            fs::set_permissions(GRUB_SNAPSHOT_SCRIPT, fs::Permissions::from_mode(0o755)).into_diagnostic()?;
+        }
+
+        run_command("update-grub", &[], "Update GRUB")?;
+        Ok(entries.len())
+    }
+}
+
+/// systemd-boot can only read the ESP, so kernels and initrds are copied to
+/// `<esp>/hammer/<kernel>/`, once per kernel version however many snapshots
+/// boot it, next to a `loader/entries/hammer-<name>.conf` per snapshot.
+struct SystemdBoot {
+    esp: std::path::PathBuf,
+}
+
+/// Space left free on the ESP after copying, for the distribution's own kernels
+const ESP_RESERVE: u64 = 128 * 1024 * 1024;
+
+impl Bootloader for SystemdBoot {
+    fn name(&self) -> &'static str {
+        "systemd-boot"
+    }
+
+    fn write_entries(&self, root_uuid: &str, entries: &[SnapshotBootEntry]) -> Result<usize> {
+        let entries_dir = self.esp.join("loader/entries");
+        let images_dir = self.esp.join("hammer");
+        fs::create_dir_all(&entries_dir).into_diagnostic()?;
+        fs::create_dir_all(&images_dir).into_diagnostic()?;
+
+        // Entries are newest first, so the newest snapshots get their kernel in if space runs out
+        let mut kernels: Vec<(&str, &Path)> = Vec::new();
+        for e in entries {
+            if !kernels.iter().any(|(kernel, _)| *kernel == e.kernel) {
+                kernels.push((&e.kernel, &e.boot_dir));
+            }
+        }
+
+        // Free the space of kernels no snapshot boots anymore before copying new ones
+        for entry in fs::read_dir(&images_dir).into_diagnostic()?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !kernels.iter().any(|(kernel, _)| *kernel == name) {
+                fs::remove_dir_all(entry.path()).into_diagnostic()?;
+            }
+        }
+
+        let mut stored = Vec::new();
+        for (kernel, boot_dir) in kernels {
+            let target = images_dir.join(kernel);
+            if target.join("vmlinuz").exists() && target.join("initrd.img").exists() {
+                stored.push(kernel);
+                continue;
+            }
+
+            let files = [(format!("vmlinuz-{}", kernel), "vmlinuz"), (format!("initrd.img-{}", kernel), "initrd.img")];
+            let size: u64 = files
+            .iter()
+            .map(|(src, _)| fs::metadata(boot_dir.join(src)).map(|m| m.len()).unwrap_or(0))
+            .sum();
+            if let Err(err) = check_free_space(&self.esp, size + ESP_RESERVE) {
+                Logger::warn(&format!("Not copying kernel {} to the ESP: {}", kernel, err));
+                continue;
+            }
+
+            fs::create_dir_all(&target).into_diagnostic()?;
+            let copied = files
+            .iter()
+            .try_for_each(|(src, dest)| fs::copy(boot_dir.join(src), target.join(dest)).map(|_| ()));
+            if let Err(err) = copied {
+                // Don't leave half a kernel behind, it would look complete to the next run
+                let _ = fs::remove_dir_all(&target);
+                Logger::warn(&format!("Copying kernel {} to the ESP failed: {}", kernel, err));
+                continue;
+            }
+            stored.push(kernel);
+        }
+
+        // Only snapshots whose kernel made it onto the ESP keep an entry
+        let bootable: Vec<&SnapshotBootEntry> = entries.iter().filter(|e| stored.contains(&e.kernel.as_str())).collect();
+        for entry in fs::read_dir(&entries_dir).into_diagnostic()?.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = file_name.strip_prefix("hammer-").and_then(|n| n.strip_suffix(".conf")) {
+                if !bootable.iter().any(|e| e.name == name) {
+                    fs::remove_file(entry.path()).into_diagnostic()?;
+                }
+            }
+        }
+
+        for e in &bootable {
+            let conf = format!(
+                "# Generated by hammer-updater, changes will be overwritten\ntitle {}\nlinux /hammer/{}/vmlinuz\ninitrd /hammer/{}/initrd.img\noptions root=UUID={} rootflags=subvol={} ro\n",
+                e.title, e.kernel, e.kernel, root_uuid, e.subvol
+            );
+            fs::write(entries_dir.join(format!("hammer-{}.conf", e.name)), conf).into_diagnostic()?;
+        }
+        Ok(bootable.len())
+    }
+}
+
+/// Picks systemd-boot when a loader.conf is present on the ESP, GRUB when
+/// /boot/grub exists, and nothing otherwise.
+fn detect_bootloader() -> Option<Box<dyn Bootloader>> {
+    let esp = run_command("bootctl", &["--print-esp-path"], "Find ESP")
+    .ok()
+    .map(|p| std::path::PathBuf::from(p.trim()))
+    .filter(|p| p.join("loader/loader.conf").exists());
+
+    if let Some(esp) = esp {
+        return Some(Box::new(SystemdBoot { esp }));
+    }
+    if Path::new("/boot/loader/loader.conf").exists() {
+        return Some(Box::new(SystemdBoot { esp: "/boot".into() }));
+    }
+    if Path::new("/boot/grub").exists() {
+        return Some(Box::new(Grub));
+    }
+    None
+}
+
+/// Regenerates the boot menu entries that boot each snapshot directly.
+/// Snapshots are only bootable when they carry their own kernel: /boot lives
+/// inside @, or a separate /boot was copied in at snapshot time. Others get no entry.
+/// Failing only warns: the operation that called it already succeeded.
+fn update_bootloader_entries(dry_run: bool) {
+    if let Err(err) = write_bootloader_entries(dry_run) {
+        Logger::warn(&format!("Could not update the snapshot boot entries: {}", err));
+        Logger::warn("The next update, clean or prune tries again.");
+    }
+}
+
+fn write_bootloader_entries(dry_run: bool) -> Result<()> {
+    let Some(bootloader) = detect_bootloader() else {
+        Logger::warn("No supported bootloader found, skipping snapshot boot entries.");
+        return Ok(());
+    };
+    if dry_run {
+        Logger::info(&format!("[dry-run] regenerate {} snapshot entries", bootloader.name()));
+        return Ok(());
+    }
+
+    let uuid = run_command("findmnt", &["-n", "-o", "UUID", "/"], "Find Root UUID")?;
+    let uuid = uuid.trim();
+    let store = SnapshotStore::load()?;
+
+    mount_btrfs_root()?;
+    let entries: Vec<SnapshotBootEntry> = store
+    .list()
+    .iter()
+    .rev()
+    .filter_map(|snap| {
+        let boot_dir = snap.path.join("boot");
+        let kernel = snapshot_boot_kernel(&boot_dir, snap.meta.as_ref())?;
+        let taken = snap
+        .timestamp
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| snap.name.clone());
+        let kind = snap.meta.as_ref().map(|m| m.kind.as_str()).filter(|k| !k.is_empty()).unwrap_or("snapshot");
+
+        Some(SnapshotBootEntry {
+            name: snap.name.clone(),
+            title: format!("Snapshot {} ({}, {})", taken, kind, kernel),
+            kernel,
+            subvol: format!("{}/{}", Paths::get().snapshots_subvol, snap.name),
+            boot_dir,
+        })
+    })
+    .collect();
+
+    let result = bootloader.write_entries(uuid, &entries);
+    umount_btrfs_root()?;
+    let listed = result?;
+
+    Logger::info(&format!("{} menu lists {} snapshot(s).", bootloader.name(), listed));
+    Ok(())
+}
+
+/// Picks the kernel a snapshot should boot: the one recorded in its metadata when
+/// it is still present, otherwise the newest kernel with an initrd in its /boot.
+fn snapshot_boot_kernel(boot_dir: &Path, meta: Option<&SnapshotMeta>) -> Option<String> {
+    let has_pair = |k: &str| {
+        boot_dir.join(format!("vmlinuz-{}", k)).exists() && boot_dir.join(format!("initrd.img-{}", k)).exists()
+    };
+
+    if let Some(kernel) = meta.map(|m| m.kernel.as_str()).filter(|k| !k.is_empty() && has_pair(k)) {
+        return Some(kernel.to_string());
+    }
+
+    fs::read_dir(boot_dir)
+    .ok()?
+    .filter_map(|e| e.ok())
+    .filter_map(|e| e.file_name().to_string_lossy().strip_prefix("vmlinuz-").map(str::to_string))
+    .filter(|k| has_pair(k))
+    .max_by(|a, b| compare_kernel_versions(a, b))
+}
+
+/// Reasons `snap_root` would not boot once it is @: no usable kernel, or the
+/// kernel the default boot entry picks has no modules in the snapshot. With a
+/// separate /boot the default entry boots the newest kernel in /boot, which may
+/// come from an update the snapshot predates.
+fn rollback_boot_problems(snap_root: &Path, meta: Option<&SnapshotMeta>) -> Vec<String> {
+    let mut problems = Vec::new();
+    let own = snapshot_boot_kernel(&snap_root.join("boot"), meta);
+
+    let default = if boot_is_separate() {
+        own.iter()
+        .cloned()
+        .chain(snapshot_boot_kernel(Path::new("/boot"), None))
+        .max_by(|a, b| compare_kernel_versions(a, b))
+    } else {
+        own.clone()
+    };
+
+    match default {
+        None => problems.push("No kernel with a matching initrd for this snapshot".to_string()),
+        Some(kernel) if !snap_root.join("lib/modules").join(&kernel).exists() => problems.push(format!(
+            "/boot would start kernel {}, but the snapshot has no modules for it", kernel
+        )),
+        Some(_) => {}
+    }
+
+    if let Some(recorded) = meta.map(|m| m.kernel.as_str()).filter(|k| !k.is_empty()) {
+        let image = format!("vmlinuz-{}", recorded);
+        if !snap_root.join("boot").join(&image).exists() && !Path::new("/boot").join(&image).exists() {
+            problems.push(format!("Recorded kernel {} is in neither the snapshot nor /boot", recorded));
+        }
+    }
+    problems
+}
+
+/// Orders kernel releases by their numeric parts, so `6.1.0-10-amd64` sorts
+/// after `6.1.0-9-amd64` where a plain string sort would put it first.
+fn compare_kernel_versions(mut a: &str, mut b: &str) -> std::cmp::Ordering {
+    let run_end = |s: &str, digits: bool| s.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(s.len());
+    loop {
+        let (Some(first_a), Some(first_b)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let (digits_a, digits_b) = (first_a.is_ascii_digit(), first_b.is_ascii_digit());
+        let (run_a, rest_a) = a.split_at(run_end(a, digits_a));
+        let (run_b, rest_b) = b.split_at(run_end(b, digits_b));
+        let order = if digits_a && digits_b {
+            let (run_a, run_b) = (run_a.trim_start_matches('0'), run_b.trim_start_matches('0'));
+            run_a.len().cmp(&run_b.len()).then_with(|| run_a.cmp(run_b))
+        } else {
+            run_a.cmp(run_b)
+        };
+        if order.is_ne() {
+            return order;
+        }
+        (a, b) = (rest_a, rest_b);
+    }
+}
+
+fn handle_diff(a: Option<String>, b: Option<&str>) -> Result<()> {
+    Logger::section("PACKAGE DIFF");
+    let a = match a {
+        Some(name) => name,
+        None => SnapshotStore::load()?
+        .latest()
+        .map(|s| s.name.clone())
+        .ok_or_else(|| HammerError::BtrfsError("No snapshots found in @snapshots".into()))?,
+    };
+    let label_b = b.unwrap_or("running system");
+
+    mount_btrfs_root()?;
+    let snap_dir = Paths::get().snapshots_dir();
+    let root_a = snap_dir.join(&a);
+    let root_b = b.map(|name| snap_dir.join(name)).unwrap_or_else(|| "/".into());
+
+    for (label, root) in [(a.as_str(), &root_a), (label_b, &root_b)] {
+        let problem = if !root.exists() {
+            Some(format!("Snapshot {} not found", label))
+        } else if !root.join("var/lib/dpkg/status").exists() {
+            Some(format!("{} has no dpkg database, cannot list its packages", label))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            umount_btrfs_root()?;
+            return Err(HammerError::BtrfsError(problem).into());
+        }
+    }
+
+    let packages = installed_packages(&root_a).and_then(|old| Ok((old, installed_packages(&root_b)?)));
+    umount_btrfs_root()?;
+    let (old, new) = packages?;
+    let changes = PackageChanges::between(&old, &new);
+
+    Logger::info(&format!("{} -> {}", a.if_supports_color(Stream::Stdout, |t| t.cyan()), label_b.if_supports_color(Stream::Stdout, |t| t.cyan())));
+    if changes.added.is_empty() && changes.removed.is_empty() && changes.changed.is_empty() {
+        Logger::success("Package sets are identical.");
+        Logger::end_section();
+        return Ok(());
+    }
+
+    let (mut upgraded, mut downgraded) = (0, 0);
+    for (name, from, to) in &changes.changed {
+        if version_is_newer(to, from) {
+            upgraded += 1;
+            println!(
+                " {} {} {} -> {}",
+                "↑".if_supports_color(Stream::Stdout, |t| t.green()),
+                name,
+                from.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                to.if_supports_color(Stream::Stdout, |t| t.green())
+            );
+        } else {
+            downgraded += 1;
+            println!(
+                " {} {} {} -> {}",
+                "↓".if_supports_color(Stream::Stdout, |t| t.yellow()),
+                name,
+                from.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+                to.if_supports_color(Stream::Stdout, |t| t.yellow())
+            );
+        }
+    }
+    for (name, version) in &changes.added {
+        println!(
+            " {} {} {}",
+            "+".if_supports_color(Stream::Stdout, |t| t.green()),
+            name.if_supports_color(Stream::Stdout, |t| t.green()),
+            version.if_supports_color(Stream::Stdout, |t| t.bright_black())
+        );
+    }
+    for (name, version) in &changes.removed {
+        println!(
+            " {} {} {}",
+            "-".if_supports_color(Stream::Stdout, |t| t.red()),
+            name.if_supports_color(Stream::Stdout, |t| t.red()),
+            version.if_supports_color(Stream::Stdout, |t| t.bright_black())
+        );
+    }
+
+    Logger::info(&format!(
+        "{} upgraded, {} downgraded, {} added, {} removed",
+        upgraded, downgraded, changes.added.len(), changes.removed.len()
+    ));
+    Logger::end_section();
+    Ok(())
+}
+
+/// Compares Debian versions with dpkg's own ordering rules.
+fn version_is_newer(candidate: &str, than: &str) -> bool {
+    run_command_status("dpkg", &["--compare-versions", candidate, "gt", than])
+    .map(|out| out.success())
+    .unwrap_or(false)
+}
+
+fn handle_diff_config(a: &str, b: Option<&str>) -> Result<()> {
+    Logger::section("APT SOURCES DIFF");
+    let label_b = b.unwrap_or("running system");
+
+    mount_btrfs_root()?;
+    let snap_dir = Paths::get().snapshots_dir();
+    let root_a = snap_dir.join(a);
+    let root_b = b.map(|name| snap_dir.join(name)).unwrap_or_else(|| "/".into());
+
+    for (label, root) in [(a, &root_a), (label_b, &root_b)] {
+        if !root.exists() {
+            umount_btrfs_root()?;
+            return Err(HammerError::BtrfsError(format!("Snapshot {} not found", label)).into());
+        }
+    }
+
+    let sources_a = apt_source_entries(&root_a);
+    let sources_b = apt_source_entries(&root_b);
+    let suite_a = system_suite(&root_a).unwrap_or_else(|| "<unknown>".into());
+    let suite_b = system_suite(&root_b).unwrap_or_else(|| "<unknown>".into());
+    umount_btrfs_root()?;
+
+    Logger::info(&format!("{} -> {}", a.if_supports_color(Stream::Stdout, |t| t.cyan()), label_b.if_supports_color(Stream::Stdout, |t| t.cyan())));
+    if suite_a != suite_b {
+        Logger::info(&format!(
+            "Suite: {} -> {}",
+            suite_a.if_supports_color(Stream::Stdout, |t| t.yellow()),
+            suite_b.if_supports_color(Stream::Stdout, |t| t.green())
+        ));
+    } else {
+        Logger::info(&format!("Suite: {}", suite_a));
+    }
+
+    let removed: Vec<&String> = sources_a.difference(&sources_b).collect();
+    let added: Vec<&String> = sources_b.difference(&sources_a).collect();
+    if removed.is_empty() && added.is_empty() {
+        Logger::success("APT sources are identical.");
+    }
+    for entry in removed {
+        println!(" {} {}", "-".if_supports_color(Stream::Stdout, |t| t.red()), entry.if_supports_color(Stream::Stdout, |t| t.red()));
+    }
+    for entry in added {
+        println!(" {} {}", "+".if_supports_color(Stream::Stdout, |t| t.green()), entry.if_supports_color(Stream::Stdout, |t| t.green()));
+    }
+
+    Logger::end_section();
+    Ok(())
+}
+
+/// Returns the APT source entries under `root` that turn off signature checks
+/// with `trusted=yes`, `allow-insecure=yes` or `allow-weak=yes`.
+fn insecure_apt_sources(root: &Path) -> Vec<String> {
+    apt_source_entries(root)
+    .into_iter()
+    .filter(|entry| {
+        let lower = entry.to_lowercase().replace(": ", "=");
+        ["trusted=yes", "allow-insecure=yes", "allow-weak=yes"].iter().any(|opt| lower.contains(opt))
+    })
+    .collect()
+}
+
+fn handle_clean_logs(keep_days: u64) -> Result<()> {
+    Logger::section("CLEANING LOGS");
+    let freed = Logger::clean(keep_days)?;
+    if freed == 0 {
+        Logger::info("Nothing to clean.");
+    } else {
+        Logger::success(&format!("Freed {} of logs.", format_size(freed)));
+    }
+    Logger::end_section();
+    Ok(())
+}
+
+/// Deletes all but the `keep` newest snapshots, oldest first by their parsed
+/// timestamp. Snapshots without one are never touched. A failed deletion
+/// doesn't stop the rest; failures are reported together at the end.
+fn handle_clean(keep: usize, dry_run: bool) -> Result<()> {
     Logger::section("CLEANING SNAPSHOTS");
-    let snapshots = btrfs_list_atomic_snapshots()?;
+    let store = SnapshotStore::load()?;
+    let snapshots = store.dated();
+    let mut failures = Vec::new();
 
-    if snapshots.len() <= 3 {
+    if snapshots.len() <= keep {
         Logger::info("Nothing to clean.");
     } else {
-        let to_delete = &snapshots[0..(snapshots.len() - 3)];
-        for snap in to_delete {
+        let to_delete = &snapshots[0..(snapshots.len() - keep)];
+        for Snapshot { name: snap, meta, .. } in to_delete {
+            if meta.as_ref().is_some_and(|m| m.pinned) {
+                Logger::info(&format!("Keeping {} (pinned)", snap));
+                continue;
+            }
+            if dry_run {
+                Logger::info(&format!(
+                    "[dry-run] btrfs subvolume delete {}",
+                    Paths::get().snapshots_dir().join(snap).display()
+                ));
+                continue;
+            }
             Logger::info(&format!("Deleting {}", snap));
-            btrfs_delete_atomic_snapshot(snap)?;
+            if let Err(e) = btrfs_delete_atomic_snapshot(snap) {
+                Logger::error(&format!("Failed to delete {}: {}", snap, e));
+                failures.push(snap.as_str());
+            }
+        }
+        update_bootloader_entries(dry_run);
+        if failures.is_empty() {
+            Logger::success("Cleanup done.");
+        }
+    }
+    Logger::end_section();
+
+    if !failures.is_empty() {
+        return Err(HammerError::BtrfsError(format!(
+            "{} snapshot(s) could not be deleted: {}", failures.len(), failures.join(", ")
+        )).into());
+    }
+    Ok(())
+}
+
+fn handle_prune(older_than: &str, keep_min: usize, dry_run: bool) -> Result<()> {
+    Logger::section("PRUNING SNAPSHOTS");
+
+    let max_age = parse_age(older_than).ok_or_else(|| {
+        HammerError::ConfigError(format!("Invalid duration '{}', expected e.g. 30d, 12h or 2w", older_than))
+    })?;
+    let cutoff = chrono::Local::now().naive_local().checked_sub_signed(max_age).ok_or_else(|| {
+        HammerError::ConfigError(format!("Duration '{}' reaches before the start of the calendar", older_than))
+    })?;
+
+    let store = SnapshotStore::load()?;
+    let snapshots = store.dated();
+    for Snapshot { name, .. } in &store.list()[snapshots.len()..] {
+        Logger::warn(&format!("Skipping {}: cannot parse its timestamp", name));
+    }
+
+    // The newest snapshots are the most likely rollback targets, never prune below keep_min
+    let protected = snapshots.len().saturating_sub(keep_min.max(1));
+    let mut reclaimed = 0;
+    let mut deleted = 0;
+
+    for Snapshot { name, meta, timestamp, .. } in &snapshots[..protected] {
+        if timestamp.is_some_and(|taken| taken >= cutoff) {
+            continue;
+        }
+        if meta.as_ref().is_some_and(|m| m.pinned) {
+            Logger::info(&format!("Keeping {} (pinned)", name));
+            continue;
+        }
+
+        let size = meta.as_ref().map(|m| m.size_bytes).unwrap_or(0);
+        if dry_run {
+            Logger::info(&format!("[dry-run] would delete {} ({})", name, format_size(size)));
+        } else {
+            Logger::info(&format!("Deleting {}", name));
+            btrfs_delete_atomic_snapshot(name)?;
+        }
+        reclaimed += size;
+        deleted += 1;
+    }
+
+    if deleted == 0 {
+        Logger::info("Nothing to prune.");
+    } else {
+        update_bootloader_entries(dry_run);
+        Logger::success(&format!(
+            "{} {} snapshot(s), up to {} of apparent size.",
+            if dry_run { "Would prune" } else { "Pruned" },
+            deleted,
+            format_size(reclaimed)
+        ));
+    }
+    Logger::end_section();
+    Ok(())
+}
+
+/// Parses durations like `90m`, `12h`, `30d` or `2w`. Zero, negative and
+/// out-of-range values give `None`.
+fn parse_age(input: &str) -> Option<chrono::Duration> {
+    let input = input.trim();
+    let unit = input.chars().last()?;
+    let value: u32 = input[..input.len() - unit.len_utf8()].parse().ok()?;
+    if value == 0 {
+        return None;
+    }
+    let value = i64::from(value);
+    match unit {
+        'm' => chrono::Duration::try_minutes(value),
+        'h' => chrono::Duration::try_hours(value),
+        'd' => chrono::Duration::try_days(value),
+        'w' => chrono::Duration::try_weeks(value),
+        _ => None,
+    }
+}
+
+/// Prints the state of the running system. The `--json` form is one object:
+///
+/// - `kernel`: running kernel release
+/// - `system_version`: SHA-256 over the installed `name=version` list
+/// - `suite`: release codename, or null
+/// - `latest_snapshot`: name of the newest restore point, or null
+/// - `update_in_progress`: whether a live hammer process holds the lock
+fn handle_status(json: bool) -> Result<()> {
+    let root = Path::new("/");
+    let kernel = run_command("uname", &["-r"], "Detect Kernel")?.trim().to_string();
+    let system_version = compute_system_version(root)?;
+    let suite = system_suite(root);
+    let latest = SnapshotStore::load_unmounted()?.latest().map(|s| s.name.clone());
+    let lock_holder = read_lock_owner(&Paths::get().lock_file).filter(|pid| process_alive(*pid));
+
+    if json {
+        let status = serde_json::json!({
+            "kernel": kernel,
+            "system_version": system_version,
+            "suite": suite,
+            "latest_snapshot": latest,
+            "update_in_progress": lock_holder.is_some(),
+        });
+        println!("{}", serde_json::to_string_pretty(&status).into_diagnostic()?);
+        return Ok(());
+    }
+
+    Logger::section("SYSTEM STATUS");
+    Logger::info(&format!("Kernel:          {}", kernel.if_supports_color(Stream::Stdout, |t| t.cyan())));
+    Logger::info(&format!("System version:  {}", system_version.chars().take(12).collect::<String>()));
+    Logger::info(&format!("Suite:           {}", suite.as_deref().unwrap_or("<unknown>")));
+    Logger::info(&format!("Restore point:   {}", latest.as_deref().unwrap_or("<none>")));
+    match lock_holder {
+        Some(pid) => Logger::warn(&format!("Operation in progress (process {})", pid)),
+        None => Logger::success("No operation in progress."),
+    }
+    Logger::end_section();
+    Ok(())
+}
+
+fn handle_manual_snapshot(label: Option<String>, description: Option<String>, dry_run: bool) -> Result<()> {
+    let label = label.unwrap_or_else(|| "manual".to_string());
+    let valid = !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid {
+        return Err(HammerError::ConfigError(format!(
+            "Invalid snapshot name '{}', use letters, digits, '-', '_' or '.'", label
+        )).into());
+    }
+
+    let snap_name = create_snapshot_name(&label);
+    let spinner = create_spinner("Snapshotting @ subvolume...");
+    snapshot(dry_run, &snap_name, "manual")?;
+    if let Some(description) = description.filter(|_| !dry_run) {
+        btrfs_update_snapshot_meta(&snap_name, |meta| meta.description = Some(description))?;
+    }
+    spinner.finish_with_message(format!("Snapshot {} created in @snapshots", snap_name));
+    Ok(())
+}
+
+/// Result of one `doctor` check, with a remediation hint unless it passed.
+enum Check {
+    Pass(String),
+    Warn(String, String),
+    Fail(String, String),
+}
+
+/// Runs the checks `update` and `rollback` depend on and prints each result.
+/// Fails if any hard precondition is not met.
+fn handle_doctor() -> Result<()> {
+    Logger::section("HAMMER DOCTOR");
+    let mut checks = Vec::new();
+    let paths = Paths::get();
+
+    let fstype = run_command("findmnt", &["-n", "-o", "FSTYPE", "/"], "Detect Root Filesystem")
+    .map(|out| out.trim().to_string())
+    .unwrap_or_default();
+    if fstype == "btrfs" {
+        checks.push(Check::Pass("Root filesystem is btrfs".into()));
+    } else {
+        checks.push(Check::Fail(
+            format!("Root filesystem is {}", if fstype.is_empty() { "unknown" } else { fstype.as_str() }),
+            "hammer needs / on btrfs with the @ subvolume layout".into(),
+        ));
+    }
+
+    if fstype == "btrfs" {
+        // Listed rather than looked up under the top-level mount, which a running update may be using
+        match run_command("btrfs", &["subvolume", "list", "/"], "List Subvolumes") {
+            Ok(output) => {
+                let subvols = parse_subvolume_list(&output);
+                if subvols.iter().any(|p| p == Path::new(&paths.root_subvol)) {
+                    checks.push(Check::Pass(format!("Subvolume {} found", paths.root_subvol)));
+                } else {
+                    checks.push(Check::Fail(
+                        format!("Subvolume {} not found", paths.root_subvol),
+                        "Hammer requires the @ layout; move / into an @ subvolume".into(),
+                    ));
+                }
+                if subvols.iter().any(|p| p == Path::new(&paths.snapshots_subvol)) {
+                    checks.push(Check::Pass(format!("Subvolume {} found", paths.snapshots_subvol)));
+                } else {
+                    checks.push(Check::Warn(
+                        format!("Subvolume {} does not exist yet", paths.snapshots_subvol),
+                        "It is created by the first update or 'hammer snapshot'".into(),
+                    ));
+                }
+            }
+            Err(e) => checks.push(Check::Fail(
+                format!("Cannot list btrfs subvolumes: {}", e),
+                "Run as root and check that / is a btrfs subvolume".into(),
+            )),
+        }
+
+        // update's default --space-headroom; / is @, so its size is what a snapshot and upgrade need
+        let free = calculate_dir_size(Path::new("/"))
+        .and_then(|root_size| check_free_space(Path::new("/"), root_size + 2048 * 1024 * 1024));
+        match free {
+            Ok(()) => checks.push(Check::Pass("Enough free space for a snapshot and upgrade".into())),
+            Err(e) => checks.push(Check::Fail(e.to_string(), "Free space with 'hammer clean' or 'hammer prune'".into())),
+        }
+    }
+
+    match fs::read_to_string(&paths.lock_file).map(|c| lock_owner(&c, process_alive)) {
+        Err(_) => checks.push(Check::Pass("No lock held".into())),
+        Ok(LockOwner::Running(pid)) => checks.push(Check::Fail(
+            format!("{} is held by running process {}", paths.lock_file.display(), pid),
+            "Wait for it to finish, or pass --force if it is stuck".into(),
+        )),
+        Ok(LockOwner::Unknown) => checks.push(Check::Fail(
+            format!("{} holds no PID", paths.lock_file.display()),
+            "Pass --force if no other hammer is running".into(),
+        )),
+        Ok(LockOwner::Stale(_)) => checks.push(Check::Warn(
+            format!("Stale lock file {}", paths.lock_file.display()),
+            "The next hammer command removes it".into(),
+        )),
+    }
+
+    let problems = boot_sanity_problems(Path::new("/"));
+    if problems.is_empty() {
+        checks.push(Check::Pass("/boot has a kernel and initrd".into()));
+    } else {
+        checks.push(Check::Fail(problems.join("; "), "Reinstall the kernel package before updating".into()));
+    }
+
+    let insecure = insecure_apt_sources(Path::new("/"));
+    if insecure.is_empty() {
+        checks.push(Check::Pass("All APT sources are signature-checked".into()));
+    } else {
+        checks.push(Check::Warn(
+            format!("{} APT source(s) skip signature checks", insecure.len()),
+            "Fix them, or pass --allow-insecure-repos to update".into(),
+        ));
+    }
+
+    let mut failed = 0;
+    for check in &checks {
+        match check {
+            Check::Pass(msg) => Logger::success(msg),
+            Check::Warn(msg, hint) => {
+                Logger::warn(msg);
+                Logger::info(&format!("  -> {}", hint));
+            }
+            Check::Fail(msg, hint) => {
+                failed += 1;
+                Logger::error(msg);
+                Logger::info(&format!("  -> {}", hint));
+            }
         }
-        Logger::success("Cleanup done.");
     }
     Logger::end_section();
+
+    if failed > 0 {
+        return Err(HammerError::CommandFailed(format!("{} doctor check(s) failed", failed)).into());
+    }
     Ok(())
 }
 
-fn handle_rollback() -> Result<()> {
+/// Recomputes a snapshot's system_version and checks it can still boot, so a
+/// rollback target can be trusted. Fails on any mismatch.
+fn handle_verify(snapshot: Option<String>) -> Result<()> {
+    let store = SnapshotStore::load()?;
+    let target = match &snapshot {
+        Some(name) => store.get(name),
+        None => store.latest(),
+    }
+    .ok_or_else(|| HammerError::BtrfsError(match &snapshot {
+        Some(name) => format!("Snapshot {} not found", name),
+        None => "No snapshots found in @snapshots".to_string(),
+    }))?;
+
+    Logger::section(&format!("VERIFY {}", target.name));
+    let mut problems = Vec::new();
+
+    mount_btrfs_root()?;
+    let snap_root = Paths::get().snapshots_dir().join(&target.name);
+    let checked = compute_system_version(&snap_root).map(|actual| {
+        let recorded = target.meta.as_ref().map(|m| m.system_version.as_str()).unwrap_or("");
+        if recorded.is_empty() {
+            Logger::warn("No system_version recorded, cannot compare the package set.");
+        } else if recorded != actual {
+            problems.push(format!(
+                "Package set changed: recorded {}, now {}",
+                &recorded[..recorded.len().min(12)],
+                &actual[..actual.len().min(12)]
+            ));
+        } else {
+            Logger::success("Package set matches the recorded system_version.");
+        }
+
+        problems.extend(rollback_boot_problems(&snap_root, target.meta.as_ref()));
+    });
+    umount_btrfs_root()?;
+    checked?;
+
+    if problems.is_empty() {
+        Logger::success("Kernel and initrd present.");
+        Logger::end_section();
+        return Ok(());
+    }
+    for problem in &problems {
+        Logger::error(problem);
+    }
+    Logger::end_section();
+    Err(HammerError::BtrfsError(format!("{} failed verification", target.name)).into())
+}
+
+/// Prints each snapshot with the one taken before it, its kernel and package
+/// set. Snapshots whose package set matches the running system are marked.
+fn handle_history() -> Result<()> {
+    let store = SnapshotStore::load()?;
+    let live_version = compute_system_version(Path::new("/"))?;
+    let short = |version: &str| version.chars().take(12).collect::<String>();
+
+    Logger::section("HISTORY");
+    for snap in store.list() {
+        let meta = snap.meta.clone().unwrap_or_default();
+        let parent = store.parent_of(snap).map(|p| p.name.as_str()).unwrap_or("-");
+        let current = !meta.system_version.is_empty() && meta.system_version == live_version;
+        println!(
+            " {} {: <32} {: <16} {: <14} after {: <32} {: <24} {: <12} {}",
+            "│".if_supports_color(Stream::Stdout, |t| t.blue()),
+            snap.name.if_supports_color(Stream::Stdout, |t| t.cyan()),
+            format_age(snap.timestamp),
+            if meta.kind.is_empty() { "<unknown>" } else { meta.kind.as_str() },
+            parent,
+            meta.kernel.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+            short(&meta.system_version),
+            if current { "= live".if_supports_color(Stream::Stdout, |t| t.green()).to_string() } else { String::new() }
+        );
+    }
+    let kernel = run_command("uname", &["-r"], "Detect Kernel")?;
+    println!(
+        " {} {: <32} {: <16} {: <14} after {: <32} {: <24} {}",
+        "└".if_supports_color(Stream::Stdout, |t| t.blue()),
+        "live system".if_supports_color(Stream::Stdout, |t| t.green().bold()),
+        "now",
+        "",
+        store.latest().map(|s| s.name.as_str()).unwrap_or("-"),
+        kernel.trim().if_supports_color(Stream::Stdout, |t| t.bright_black()),
+        short(&live_version)
+    );
+    Logger::end_section();
+    Ok(())
+}
+
+fn handle_pin(name: &str, pinned: bool) -> Result<()> {
+    btrfs_update_snapshot_meta(name, |meta| meta.pinned = pinned)?;
+    if pinned {
+        Logger::success(&format!("Pinned {}; clean and prune will keep it.", name));
+    } else {
+        Logger::success(&format!("Unpinned {}.", name));
+    }
+    Ok(())
+}
+
+fn handle_list(json: bool) -> Result<()> {
+    let store = SnapshotStore::load()?;
+    let snapshots = store.list();
+
+    if json {
+        let entries: Vec<serde_json::Value> = snapshots
+        .iter()
+        .map(|Snapshot { name, meta, .. }| serde_json::json!({
+            "name": name,
+            "kind": meta.as_ref().map(|m| m.kind.as_str()),
+            "kernel": meta.as_ref().map(|m| m.kernel.as_str()),
+            "system_version": meta.as_ref().map(|m| m.system_version.as_str()).filter(|v| !v.is_empty()),
+            "created_at": meta.as_ref().map(|m| m.created_at.as_str()).filter(|c| !c.is_empty()),
+            "size_bytes": meta.as_ref().map(|m| m.size_bytes).filter(|s| *s > 0),
+            "suite": meta.as_ref().map(|m| m.suite.as_str()).filter(|s| !s.is_empty()),
+            "apt_sources": meta.as_ref().map(|m| &m.apt_sources).filter(|s| !s.is_empty()),
+            "pinned": meta.as_ref().is_some_and(|m| m.pinned),
+            "description": meta.as_ref().and_then(|m| m.description.as_deref()),
+        }))
+        .collect();
+        println!("{}", serde_json::to_string_pretty(&entries).into_diagnostic()?);
+        return Ok(());
+    }
+
+    Logger::section("SNAPSHOTS");
+    if snapshots.is_empty() {
+        Logger::info("No snapshots found in @snapshots.");
+    }
+    for Snapshot { name, meta, timestamp, .. } in snapshots {
+        let field = |value: Option<String>| value.filter(|v| !v.is_empty()).unwrap_or_else(|| "<unknown>".to_string());
+        let kind = field(meta.as_ref().map(|m| m.kind.clone()));
+        let kernel = field(meta.as_ref().map(|m| m.kernel.clone()));
+        let version = field(meta.as_ref().map(|m| m.system_version.chars().take(12).collect()));
+        let created = format_age(*timestamp);
+        let size = field(meta.as_ref().filter(|m| m.size_bytes > 0).map(|m| format_size(m.size_bytes)));
+        let pin = if meta.as_ref().is_some_and(|m| m.pinned) { "pinned" } else { "" };
+
+        println!(
+            " {} {: <32} {: <14} {: <16} {: <10} {: <12} {: <24} {}",
+            "│".if_supports_color(Stream::Stdout, |t| t.blue()),
+            name.if_supports_color(Stream::Stdout, |t| t.cyan()),
+            kind,
+            created,
+            size,
+            version,
+            kernel.if_supports_color(Stream::Stdout, |t| t.bright_black()),
+            pin.if_supports_color(Stream::Stdout, |t| t.yellow())
+        );
+        if let Some(description) = meta.as_ref().and_then(|m| m.description.as_deref()) {
+            println!(
+                " {}   {}",
+                "│".if_supports_color(Stream::Stdout, |t| t.blue()),
+                description.if_supports_color(Stream::Stdout, |t| t.italic())
+            );
+        }
+    }
+    Logger::end_section();
+    Ok(())
+}
+
+fn handle_snapshot_boot(snapshot: Option<String>) -> Result<()> {
+    Logger::section("CAPTURE /boot INTO SNAPSHOTS");
+
+    if !boot_is_separate() {
+        Logger::info("/boot is part of @, snapshots already carry their kernels.");
+        Logger::end_section();
+        return Ok(());
+    }
+
+    let targets = match snapshot {
+        Some(name) => vec![name],
+        None => btrfs_list_atomic_snapshots()?,
+    };
+
+    let mut total = 0;
+    for snap in &targets {
+        let copied = btrfs_capture_boot(snap)?;
+        Logger::info(&format!("{}: copied {} file(s)", snap, copied));
+        total += copied;
+    }
+
+    Logger::success(&format!("Copied {} file(s) into {} snapshot(s)", total, targets.len()));
+    Logger::end_section();
+    update_bootloader_entries(false);
+    Ok(())
+}
+
+fn handle_vacuum_logs(snapshot: Option<String>) -> Result<()> {
+    Logger::section("VACUUM SNAPSHOT LOGS");
+
+    let targets = match snapshot {
+        Some(name) => vec![name],
+        None => btrfs_list_atomic_snapshots()?,
+    };
+
+    if targets.is_empty() {
+        Logger::info("No snapshots found in @snapshots.");
+        Logger::end_section();
+        return Ok(());
+    }
+
+    let mut total = 0;
+    for snap in &targets {
+        let reclaimed = btrfs_vacuum_snapshot_logs(snap)?;
+        Logger::info(&format!("{}: reclaimed {}", snap, format_size(reclaimed)));
+        total += reclaimed;
+    }
+
+    Logger::success(&format!("Vacuumed {} snapshot(s), reclaimed {}", targets.len(), format_size(total)));
+    Logger::end_section();
+    Ok(())
+}
+
+fn handle_rollback(steps: Option<usize>, yes: bool, confirm_reboot_safe: bool) -> Result<()> {
     Logger::section("SYSTEM ROLLBACK");
-    let snapshots = btrfs_list_atomic_snapshots()?;
+
+    // Without a terminal nobody can answer the prompts, so the choice must be explicit
+    if !std::io::stdin().is_terminal() && (steps.is_none() || !yes) {
+        return Err(HammerError::ConfigError(
+            "stdin is not a terminal; run 'hammer rollback <n> --yes' to roll back unattended".into()
+        ).into());
+    }
+
+    let store = SnapshotStore::load()?;
+    let snapshots: Vec<&str> = store.list().iter().map(|s| s.name.as_str()).collect();
+    let dated = store.dated().len();
+    let items: Vec<String> = store
+    .list()
+    .iter()
+    .map(|s| format!("{}  ({})", s.name, format_age(s.timestamp)))
+    .collect();
 
     if snapshots.is_empty() {
         Logger::error("No snapshots found in @snapshots.");
         return Ok(());
     }
 
-    let selection = Select::new()
-    .with_prompt("Select snapshot to restore")
-    .items(&snapshots)
-    .default(snapshots.len() - 1)
-    .interact()
-    .into_diagnostic()?;
+    let selection = match steps {
+        Some(n) if n == 0 || n > dated => {
+            return Err(HammerError::ConfigError(format!(
+                "Cannot step back {} snapshot(s), {} available", n, dated
+            )).into());
+        }
+        Some(n) => dated - n,
+        None => Select::new()
+        .with_prompt("Select snapshot to restore")
+        .items(&items)
+        .default(dated.saturating_sub(1))
+        .interact()
+        .into_diagnostic()?,
+    };
 
-    let target = &snapshots[selection];
+    let target = snapshots[selection];
+    let meta = store.get(target).and_then(|s| s.meta.clone());
 
-    Logger::warn(&format!("Target: {}", target.yellow()));
+    Logger::warn(&format!("Target: {}", target.if_supports_color(Stream::Stdout, |t| t.yellow())));
+    if let Some(meta) = &meta {
+        Logger::info(&format!("  Kind:     {}", meta.kind));
+        Logger::info(&format!("  Created:  {}", meta.created_at));
+        Logger::info(&format!("  Kernel:   {}", meta.kernel));
+        Logger::info(&format!("  Suite:    {}", meta.suite));
+        Logger::info(&format!("  Version:  {}", meta.system_version.chars().take(12).collect::<String>()));
+        if !meta.system_version.is_empty() && meta.system_version == compute_system_version(Path::new("/"))? {
+            Logger::warn("This snapshot has the same package set as the running system; only files outside packages differ.");
+        }
+    }
     Logger::warn("To restore: The system will rename current '@' to '@bad-date' and restore snapshot to '@'.");
     Logger::warn("REBOOT IS REQUIRED IMMEDIATELY AFTER.");
 
-    if Confirm::new().with_prompt("Proceed?").interact().into_diagnostic()? {
-        use hammer_core::{mount_btrfs_root, umount_btrfs_root, MOUNT_POINT};
-        use std::path::Path;
-
+    if yes || Confirm::new().with_prompt("Proceed?").default(false).interact().into_diagnostic()? {
         let spinner = create_spinner("Performing rollback...");
         mount_btrfs_root()?;
 
-        // 1. Rename current @
         let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
-        let bad_name = format!("@bad-{}", timestamp);
-        let root = Path::new(MOUNT_POINT);
+        let paths = Paths::get();
+        let live_root = paths.root_subvol_path();
+        let bad_root = paths.mount_point.join(format!("@bad-{}", timestamp));
+        let staged_root = paths.mount_point.join(format!("@rollback-{}", timestamp));
+        let snap_src = paths.snapshots_dir().join(target);
+
+        // A half-deleted or hand-copied entry in @snapshots would leave @ unbootable
+        if run_command("btrfs", &["subvolume", "show", &snap_src.to_string_lossy()], "Check Subvolume").is_err() {
+            umount_btrfs_root()?;
+            return Err(HammerError::BtrfsError(format!("{} is not a btrfs subvolume", target)).into());
+        }
 
-        run_command("mv", &[
-            &root.join("@").to_string_lossy(),
-                    &root.join(&bad_name).to_string_lossy()
-        ], "Rename current @")?;
+        let problems = rollback_boot_problems(&snap_src, meta.as_ref());
+        if !problems.is_empty() {
+            for problem in &problems {
+                Logger::error(problem);
+            }
+            if !confirm_reboot_safe {
+                umount_btrfs_root()?;
+                return Err(HammerError::BtrfsError(format!(
+                    "{} may not boot. Run 'hammer snapshot-boot' to copy /boot into it, or pass --confirm-reboot-safe to switch anyway.",
+                    target
+                )).into());
+            }
+            Logger::warn("--confirm-reboot-safe given, switching anyway.");
+        }
+
+        for path in [&bad_root, &staged_root] {
+            if path.exists() {
+                umount_btrfs_root()?;
+                return Err(HammerError::BtrfsError(format!("{} already exists, refusing to overwrite it", path.display())).into());
+            }
+        }
 
-        // 2. Snapshot target to @
-        let snap_src = root.join("@snapshots").join(target);
-        let new_root = root.join("@");
+        // 1. Snapshot the target next to @ first, so the slow part happens while @ is untouched
+        btrfs_snapshot_tree(&snap_src, &staged_root)?;
 
-        run_command("btrfs", &[
-            "subvolume", "snapshot",
-            &snap_src.to_string_lossy(),
-                    &new_root.to_string_lossy()
-        ], "Restore Snapshot to @")?;
+        // 2. Swap it in with two renames, leaving @ missing only between them
+        fs::rename(&live_root, &bad_root).into_diagnostic()?;
+        if let Err(e) = fs::rename(&staged_root, &live_root) {
+            // Put the old root back rather than leave the system without @
+            let _ = fs::rename(&bad_root, &live_root);
+            umount_btrfs_root()?;
+            return Err(e).into_diagnostic();
+        }
+
+        // The separate /boot was not rolled back with @, make sure it still has the restored kernels
+        if boot_is_separate() {
+            let copied = copy_boot_images(&live_root.join("boot"), Path::new("/boot"))?;
+            if copied > 0 {
+                Logger::info(&format!("Restored {} kernel file(s) to /boot", copied));
+            }
+        }
 
         umount_btrfs_root()?;
         spinner.finish_with_message("Rollback applied.");
@@ -225,3 +2004,165 @@ fn handle_rollback() -> Result<()> {
     Logger::end_section();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // No PID reaches u32::MAX, pid_max is at most 2^22
+    const DEAD_PID: u32 = u32::MAX;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("hammer-updater-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_age_accepts_units() {
+        assert_eq!(parse_age("90m"), Some(chrono::Duration::minutes(90)));
+        assert_eq!(parse_age(" 12h "), Some(chrono::Duration::hours(12)));
+        assert_eq!(parse_age("30d"), Some(chrono::Duration::days(30)));
+        assert_eq!(parse_age("2w"), Some(chrono::Duration::weeks(2)));
+    }
+
+    #[test]
+    fn parse_age_rejects_bad_values() {
+        for input in ["", "d", "-1d", "0d", "1y", "1.5d", "4294967295w", "99999999999d"] {
+            assert_eq!(parse_age(input), None, "{}", input);
+        }
+    }
+
+    #[test]
+    fn apt_signature_problems_are_found() {
+        let stderr = "\
+W: GPG error: http://deb.example.org stable InRelease: The following signatures couldn't be verified because the public key is not available: NO_PUBKEY 0123456789ABCDEF
+E: The repository 'http://deb.example.org stable InRelease' is not signed.
+N: Updating from such a repository can't be done securely, and is therefore disabled by default.
+W: GPG error: http://other.example.org stable InRelease: The following signatures were invalid: EXPKEYSIG FEDCBA9876543210 Example
+W: Failed to fetch http://mirror.example.org/dists/stable/InRelease  Temporary failure resolving 'mirror.example.org'
+";
+        let problems = apt_signature_problems(stderr);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[1].starts_with("E: The repository"));
+        assert!(apt_signature_problems("W: Failed to fetch http://mirror.example.org\n").is_empty());
+    }
+
+    #[test]
+    fn kernel_versions_compare_numerically() {
+        use std::cmp::Ordering;
+        assert_eq!(compare_kernel_versions("6.1.0-10-amd64", "6.1.0-9-amd64"), Ordering::Greater);
+        assert_eq!(compare_kernel_versions("6.9.12", "6.10.1"), Ordering::Less);
+        assert_eq!(compare_kernel_versions("6.1.0-9-amd64", "6.1.0-9-amd64"), Ordering::Equal);
+        assert_eq!(compare_kernel_versions("6.1.0", "6.1.0-1"), Ordering::Less);
+    }
+
+    #[test]
+    fn snapshot_boot_kernel_picks_newest_pair() {
+        let boot = temp_dir("boot-kernel");
+        for kernel in ["6.1.0-9-amd64", "6.1.0-10-amd64", "6.1.0-11-amd64"] {
+            fs::write(boot.join(format!("vmlinuz-{}", kernel)), "").unwrap();
+        }
+        for kernel in ["6.1.0-9-amd64", "6.1.0-10-amd64"] {
+            fs::write(boot.join(format!("initrd.img-{}", kernel)), "").unwrap();
+        }
+        // 11 has no initrd, 10 must win over 9
+        assert_eq!(snapshot_boot_kernel(&boot, None).as_deref(), Some("6.1.0-10-amd64"));
+        let _ = fs::remove_dir_all(boot);
+    }
+
+    #[test]
+    fn systemd_boot_stores_each_kernel_once() {
+        let dir = temp_dir("esp");
+        let esp = dir.join("esp");
+        fs::create_dir_all(esp.join("hammer/old-snapshot")).unwrap();
+        let entries: Vec<SnapshotBootEntry> = ["b", "a"]
+        .iter()
+        .map(|name| {
+            let boot_dir = dir.join(name).join("boot");
+            fs::create_dir_all(&boot_dir).unwrap();
+            fs::write(boot_dir.join("vmlinuz-6.1.0-10-amd64"), "kernel").unwrap();
+            fs::write(boot_dir.join("initrd.img-6.1.0-10-amd64"), "initrd").unwrap();
+            SnapshotBootEntry {
+                name: name.to_string(),
+                kernel: "6.1.0-10-amd64".into(),
+                title: format!("Snapshot {}", name),
+                subvol: format!("@snapshots/{}", name),
+                boot_dir,
+            }
+        })
+        .collect();
+
+        let listed = SystemdBoot { esp: esp.clone() }.write_entries("uuid", &entries).unwrap();
+        assert_eq!(listed, 2);
+        let images: Vec<_> = fs::read_dir(esp.join("hammer")).unwrap().flatten().map(|e| e.file_name()).collect();
+        assert_eq!(images, ["6.1.0-10-amd64"]);
+        let conf = fs::read_to_string(esp.join("loader/entries/hammer-a.conf")).unwrap();
+        assert!(conf.contains("linux /hammer/6.1.0-10-amd64/vmlinuz"));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn lock_owner_classifies_pids() {
+        assert_eq!(lock_owner("42\n", |_| true), LockOwner::Running(42));
+        assert_eq!(lock_owner("42", |_| false), LockOwner::Stale(42));
+        assert_eq!(lock_owner("", |_| false), LockOwner::Unknown);
+        assert_eq!(lock_owner("not-a-pid", |_| false), LockOwner::Unknown);
+        assert_eq!(lock_owner(&DEAD_PID.to_string(), process_alive), LockOwner::Stale(DEAD_PID));
+        let own = std::process::id();
+        assert_eq!(lock_owner(&own.to_string(), process_alive), LockOwner::Running(own));
+    }
+
+    #[test]
+    fn stale_lock_is_taken_over() {
+        let dir = temp_dir("lock");
+        let path: &'static Path = Box::leak(dir.join("hammer.lock").into_boxed_path());
+        fs::write(path, DEAD_PID.to_string()).unwrap();
+
+        let guard = acquire_lock(path, false).unwrap();
+        assert_eq!(read_lock_owner(path), Some(std::process::id()));
+        drop(guard);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn live_lock_is_refused_without_force() {
+        let dir = temp_dir("lock-live");
+        let path: &'static Path = Box::leak(dir.join("hammer.lock").into_boxed_path());
+        fs::write(path, std::process::id().to_string()).unwrap();
+
+        assert!(acquire_lock(path, false).is_err());
+        assert!(path.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn empty_lock_is_held() {
+        let dir = temp_dir("lock-empty");
+        let path: &'static Path = Box::leak(dir.join("hammer.lock").into_boxed_path());
+        fs::write(path, "").unwrap();
+
+        assert!(acquire_lock(path, false).is_err());
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+        let guard = acquire_lock(path, true).unwrap();
+        assert_eq!(read_lock_owner(path), Some(std::process::id()));
+        drop(guard);
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn lock_taken_over_by_another_run_is_kept() {
+        let dir = temp_dir("lock-taken");
+        let path: &'static Path = Box::leak(dir.join("hammer.lock").into_boxed_path());
+
+        let guard = acquire_lock(path, false).unwrap();
+        fs::write(path, "1").unwrap();
+        drop(guard);
+        assert_eq!(read_lock_owner(path), Some(1));
+        let _ = fs::remove_dir_all(dir);
+    }
+}